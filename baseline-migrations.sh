@@ -0,0 +1,44 @@
+#!/bin/bash
+
+# Records migrations that were applied to a database by hand, so the backend
+# can take over from there and only apply the ones that came later.
+#
+# Usage: DATABASE_URL=postgres://... ./baseline-migrations.sh <last-applied-version>
+#
+# <last-applied-version> is the timestamp prefix of the newest file in
+# expenses_backend/migrations/ that has been run against the database, e.g.
+# 20250902144339 if only the first one was.
+
+set -euo pipefail
+
+LAST_VERSION="${1:?usage: $0 <last-applied-version>}"
+: "${DATABASE_URL:?DATABASE_URL must be set}"
+MIGRATIONS_DIR="$(dirname "$0")/expenses_backend/migrations"
+
+psql "$DATABASE_URL" -v ON_ERROR_STOP=1 -q <<'SQL'
+CREATE TABLE IF NOT EXISTS _sqlx_migrations (
+    version BIGINT PRIMARY KEY,
+    description TEXT NOT NULL,
+    installed_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    success BOOLEAN NOT NULL,
+    checksum BYTEA NOT NULL,
+    execution_time BIGINT NOT NULL
+);
+SQL
+
+for file in "$MIGRATIONS_DIR"/*.sql; do
+    name="$(basename "$file" .sql)"
+    version="${name%%_*}"
+    if [ "$version" -gt "$LAST_VERSION" ]; then
+        continue
+    fi
+    description="${name#*_}"
+    description="${description//_/ }"
+    checksum="$(sha384sum "$file" | cut -d' ' -f1)"
+
+    psql "$DATABASE_URL" -v ON_ERROR_STOP=1 -q -c \
+        "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+         VALUES ($version, '$description', TRUE, decode('$checksum', 'hex'), 0)
+         ON CONFLICT (version) DO NOTHING"
+    echo "Recorded $name"
+done
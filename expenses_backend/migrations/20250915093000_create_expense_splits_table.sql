@@ -0,0 +1,32 @@
+-- How an expense was divided between participants
+ALTER TABLE expenses ADD COLUMN split_type VARCHAR(20) NOT NULL DEFAULT 'equal';
+
+-- Expense splits (each participant's portion of an expense)
+CREATE TABLE expense_splits (
+    expense_id UUID REFERENCES expenses(id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users(id),
+    amount DECIMAL(10,2) NOT NULL,
+    share DECIMAL(10,4),
+    PRIMARY KEY (expense_id, user_id)
+);
+
+-- Backfill existing expenses with an equal split across the current members,
+-- giving the leftover cent to the first member by user id
+INSERT INTO expense_splits (expense_id, user_id, amount)
+SELECT e.id,
+       gm.user_id,
+       ROUND(e.amount / c.member_count, 2)
+           + CASE WHEN gm.position = 1
+                  THEN e.amount - ROUND(e.amount / c.member_count, 2) * c.member_count
+                  ELSE 0
+             END
+FROM expenses e
+JOIN (
+    SELECT group_id, user_id, ROW_NUMBER() OVER (PARTITION BY group_id ORDER BY user_id) AS position
+    FROM group_members
+) gm ON gm.group_id = e.group_id
+JOIN (
+    SELECT group_id, COUNT(*) AS member_count
+    FROM group_members
+    GROUP BY group_id
+) c ON c.group_id = e.group_id;
@@ -0,0 +1,16 @@
+-- Track edits and soft deletes on expenses
+ALTER TABLE expenses ADD COLUMN updated_at TIMESTAMPTZ;
+ALTER TABLE expenses ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE expenses ADD COLUMN deleted_by UUID REFERENCES users(id);
+
+-- Expense audit log (the state of an expense before each change)
+CREATE TABLE expense_audit (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    expense_id UUID REFERENCES expenses(id) ON DELETE CASCADE,
+    action VARCHAR(20) NOT NULL,
+    changed_by UUID REFERENCES users(id),
+    amount DECIMAL(10,2) NOT NULL,
+    description VARCHAR(255) NOT NULL,
+    split_type VARCHAR(20) NOT NULL,
+    changed_at TIMESTAMPTZ DEFAULT NOW()
+);
@@ -0,0 +1,25 @@
+-- Each group keeps its balances in one base currency
+ALTER TABLE groups ADD COLUMN currency VARCHAR(3) NOT NULL DEFAULT 'INR';
+
+-- Expenses and payments are recorded in their own currency
+ALTER TABLE expenses ADD COLUMN currency VARCHAR(3);
+UPDATE expenses e SET currency = g.currency FROM groups g WHERE e.group_id = g.id;
+UPDATE expenses SET currency = 'INR' WHERE currency IS NULL;
+ALTER TABLE expenses ALTER COLUMN currency SET NOT NULL;
+
+ALTER TABLE payments ADD COLUMN currency VARCHAR(3);
+UPDATE payments p SET currency = g.currency FROM groups g WHERE p.group_id = g.id;
+UPDATE payments SET currency = 'INR' WHERE currency IS NULL;
+ALTER TABLE payments ALTER COLUMN currency SET NOT NULL;
+
+-- Exchange rates (1 unit of base_currency = rate units of quote_currency)
+CREATE TABLE exchange_rates (
+    base_currency VARCHAR(3) NOT NULL,
+    quote_currency VARCHAR(3) NOT NULL,
+    rate DECIMAL(18,8) NOT NULL,
+    effective_date DATE NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    PRIMARY KEY (base_currency, quote_currency, effective_date)
+);
+
+ALTER TABLE expense_audit ADD COLUMN currency VARCHAR(3);
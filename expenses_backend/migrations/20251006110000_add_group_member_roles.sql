@@ -0,0 +1,15 @@
+-- Group-scoped roles: owner, manager, member or viewer
+ALTER TABLE group_members ADD COLUMN role VARCHAR(20) NOT NULL DEFAULT 'member';
+
+-- Whoever created a group owns it, joining it if they were not a member yet
+UPDATE group_members gm SET role = 'owner'
+FROM groups g
+WHERE gm.group_id = g.id AND gm.user_id = g.created_by;
+
+INSERT INTO group_members (group_id, user_id, role)
+SELECT g.id, g.created_by, 'owner'
+FROM groups g
+WHERE g.created_by IS NOT NULL
+  AND NOT EXISTS (
+      SELECT 1 FROM group_members gm WHERE gm.group_id = g.id AND gm.user_id = g.created_by
+  );
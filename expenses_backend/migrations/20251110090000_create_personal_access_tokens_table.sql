@@ -0,0 +1,18 @@
+-- Long-lived tokens for scripts and integrations. Only hashes are stored;
+-- the prefix is kept so users can tell their tokens apart. A NULL group_ids
+-- means the token works for every group the user belongs to.
+CREATE TABLE personal_access_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    token_prefix VARCHAR(12) NOT NULL,
+    token_hash VARCHAR(64) UNIQUE NOT NULL,
+    scope VARCHAR(10) NOT NULL DEFAULT 'read',
+    group_ids UUID[],
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_personal_access_tokens_user_id ON personal_access_tokens(user_id);
@@ -0,0 +1,164 @@
+-- Tighten the core tables: required columns become NOT NULL, amounts must be
+-- positive, and every foreign key spells out what happens on delete.
+--
+-- Deleting a group removes everything that belongs to it. Deleting a user is
+-- refused while they still appear in any group's money history; their
+-- memberships go with them, and references that are only a record of who
+-- did something are cleared.
+
+UPDATE users SET is_admin = FALSE WHERE is_admin IS NULL;
+UPDATE users SET created_at = NOW() WHERE created_at IS NULL;
+UPDATE groups SET created_at = NOW() WHERE created_at IS NULL;
+-- A group without a recorded creator is credited to one of its owners, then
+-- to any other member, then to whoever paid its first expense
+UPDATE groups g SET created_by = COALESCE(
+    (
+        SELECT gm.user_id FROM group_members gm
+        WHERE gm.group_id = g.id
+        ORDER BY CASE gm.role WHEN 'owner' THEN 0 WHEN 'manager' THEN 1 ELSE 2 END, gm.user_id
+        LIMIT 1
+    ),
+    (
+        SELECT e.paid_by FROM expenses e
+        WHERE e.group_id = g.id AND e.paid_by IS NOT NULL
+        ORDER BY e.created_at, e.id
+        LIMIT 1
+    )
+) WHERE created_by IS NULL;
+UPDATE expenses SET created_at = NOW() WHERE created_at IS NULL;
+UPDATE payments SET created_at = NOW() WHERE created_at IS NULL;
+UPDATE expense_audit SET changed_at = NOW() WHERE changed_at IS NULL;
+UPDATE exchange_rates SET created_at = NOW() WHERE created_at IS NULL;
+
+-- Anything left that breaks the new rules is money history, so it is not
+-- fixed up or dropped here. The migration stops and lists what an operator
+-- has to look at; once those rows are corrected it runs on the next start.
+DO $$
+DECLARE
+    problems TEXT[] := ARRAY[]::TEXT[];
+    n BIGINT;
+BEGIN
+    SELECT COUNT(*) INTO n FROM groups WHERE created_by IS NULL;
+    IF n > 0 THEN
+        problems := problems || format('%s group(s) with no creator, members or expenses', n);
+    END IF;
+    SELECT COUNT(*) INTO n FROM expenses WHERE group_id IS NULL OR paid_by IS NULL;
+    IF n > 0 THEN
+        problems := problems || format('%s expense(s) without a group or payer', n);
+    END IF;
+    SELECT COUNT(*) INTO n FROM expenses WHERE amount <= 0;
+    IF n > 0 THEN
+        problems := problems || format('%s expense(s) with an amount of zero or less', n);
+    END IF;
+    SELECT COUNT(*) INTO n FROM expense_splits WHERE amount < 0;
+    IF n > 0 THEN
+        problems := problems || format('%s expense split(s) with a negative amount', n);
+    END IF;
+    SELECT COUNT(*) INTO n FROM expense_audit WHERE expense_id IS NULL;
+    IF n > 0 THEN
+        problems := problems || format('%s expense audit entr(ies) without an expense', n);
+    END IF;
+    SELECT COUNT(*) INTO n FROM payments
+    WHERE group_id IS NULL OR from_user_id IS NULL OR to_user_id IS NULL;
+    IF n > 0 THEN
+        problems := problems || format('%s payment(s) without a group, payer or payee', n);
+    END IF;
+    SELECT COUNT(*) INTO n FROM payments WHERE amount <= 0;
+    IF n > 0 THEN
+        problems := problems || format('%s payment(s) with an amount of zero or less', n);
+    END IF;
+    SELECT COUNT(*) INTO n FROM payments WHERE from_user_id = to_user_id;
+    IF n > 0 THEN
+        problems := problems || format('%s payment(s) from a user to themselves', n);
+    END IF;
+    SELECT COUNT(*) INTO n FROM exchange_rates WHERE rate <= 0;
+    IF n > 0 THEN
+        problems := problems || format('%s exchange rate(s) of zero or less', n);
+    END IF;
+
+    IF cardinality(problems) > 0 THEN
+        RAISE EXCEPTION 'Existing data breaks the new schema constraints: %',
+            array_to_string(problems, '; ')
+            USING HINT = 'Correct or remove these rows by hand, then restart the server';
+    END IF;
+END $$;
+
+-- Users
+ALTER TABLE users
+    ALTER COLUMN is_admin SET NOT NULL,
+    ALTER COLUMN created_at SET NOT NULL;
+
+-- Groups
+ALTER TABLE groups
+    ALTER COLUMN created_by SET NOT NULL,
+    ALTER COLUMN created_at SET NOT NULL,
+    DROP CONSTRAINT groups_created_by_fkey,
+    ADD CONSTRAINT groups_created_by_fkey
+        FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE RESTRICT;
+
+ALTER TABLE group_members
+    DROP CONSTRAINT group_members_group_id_fkey,
+    ADD CONSTRAINT group_members_group_id_fkey
+        FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE,
+    DROP CONSTRAINT group_members_user_id_fkey,
+    ADD CONSTRAINT group_members_user_id_fkey
+        FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE group_invitations
+    DROP CONSTRAINT group_invitations_created_by_fkey,
+    ADD CONSTRAINT group_invitations_created_by_fkey
+        FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE;
+
+-- Expenses; descriptions are as long as the API accepts
+ALTER TABLE expenses
+    ALTER COLUMN group_id SET NOT NULL,
+    ALTER COLUMN paid_by SET NOT NULL,
+    ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN description TYPE VARCHAR(500),
+    ADD CONSTRAINT expenses_amount_check CHECK (amount > 0),
+    DROP CONSTRAINT expenses_group_id_fkey,
+    ADD CONSTRAINT expenses_group_id_fkey
+        FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE,
+    DROP CONSTRAINT expenses_paid_by_fkey,
+    ADD CONSTRAINT expenses_paid_by_fkey
+        FOREIGN KEY (paid_by) REFERENCES users(id) ON DELETE RESTRICT,
+    DROP CONSTRAINT expenses_deleted_by_fkey,
+    ADD CONSTRAINT expenses_deleted_by_fkey
+        FOREIGN KEY (deleted_by) REFERENCES users(id) ON DELETE SET NULL;
+
+ALTER TABLE expense_splits
+    ADD CONSTRAINT expense_splits_amount_check CHECK (amount >= 0),
+    DROP CONSTRAINT expense_splits_user_id_fkey,
+    ADD CONSTRAINT expense_splits_user_id_fkey
+        FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE RESTRICT;
+
+ALTER TABLE expense_audit
+    ALTER COLUMN expense_id SET NOT NULL,
+    ALTER COLUMN changed_at SET NOT NULL,
+    ALTER COLUMN description TYPE VARCHAR(500),
+    DROP CONSTRAINT expense_audit_changed_by_fkey,
+    ADD CONSTRAINT expense_audit_changed_by_fkey
+        FOREIGN KEY (changed_by) REFERENCES users(id) ON DELETE SET NULL;
+
+-- Payments
+ALTER TABLE payments
+    ALTER COLUMN group_id SET NOT NULL,
+    ALTER COLUMN from_user_id SET NOT NULL,
+    ALTER COLUMN to_user_id SET NOT NULL,
+    ALTER COLUMN created_at SET NOT NULL,
+    ADD CONSTRAINT payments_amount_check CHECK (amount > 0),
+    ADD CONSTRAINT payments_distinct_users_check CHECK (from_user_id <> to_user_id),
+    DROP CONSTRAINT payments_group_id_fkey,
+    ADD CONSTRAINT payments_group_id_fkey
+        FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE,
+    DROP CONSTRAINT payments_from_user_id_fkey,
+    ADD CONSTRAINT payments_from_user_id_fkey
+        FOREIGN KEY (from_user_id) REFERENCES users(id) ON DELETE RESTRICT,
+    DROP CONSTRAINT payments_to_user_id_fkey,
+    ADD CONSTRAINT payments_to_user_id_fkey
+        FOREIGN KEY (to_user_id) REFERENCES users(id) ON DELETE RESTRICT;
+
+-- Exchange rates
+ALTER TABLE exchange_rates
+    ALTER COLUMN created_at SET NOT NULL,
+    ADD CONSTRAINT exchange_rates_rate_check CHECK (rate > 0);
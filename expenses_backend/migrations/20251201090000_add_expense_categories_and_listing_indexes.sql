@@ -0,0 +1,10 @@
+-- Optional label an expense can be filed under, such as "food" or "travel"
+ALTER TABLE expenses ADD COLUMN category VARCHAR(50);
+ALTER TABLE expense_audit ADD COLUMN category VARCHAR(50);
+
+-- The expense list pages through a group's live expenses by date or by
+-- amount, with the id breaking ties
+CREATE INDEX idx_expenses_group_id_created_at ON expenses(group_id, created_at, id)
+    WHERE deleted_at IS NULL;
+CREATE INDEX idx_expenses_group_id_amount ON expenses(group_id, amount, id)
+    WHERE deleted_at IS NULL;
@@ -0,0 +1,21 @@
+-- When an expense actually happened, which can be well before it was entered.
+-- `incurred_on` is the calendar date where it was paid; `incurred_at` is the
+-- exact moment when a time was given, and `time_zone` the payer's zone.
+ALTER TABLE expenses
+    ADD COLUMN incurred_on DATE,
+    ADD COLUMN incurred_at TIMESTAMPTZ,
+    ADD COLUMN time_zone VARCHAR(64);
+
+-- Until now the only date an expense had was when it was recorded
+UPDATE expenses SET incurred_on = (created_at AT TIME ZONE 'UTC')::DATE;
+
+ALTER TABLE expenses ALTER COLUMN incurred_on SET NOT NULL;
+
+ALTER TABLE expense_audit
+    ADD COLUMN incurred_on DATE,
+    ADD COLUMN incurred_at TIMESTAMPTZ,
+    ADD COLUMN time_zone VARCHAR(64);
+
+-- Expenses on the same day list in the order they were entered
+CREATE INDEX idx_expenses_group_id_incurred_on ON expenses(group_id, incurred_on, created_at, id)
+    WHERE deleted_at IS NULL;
@@ -0,0 +1,13 @@
+-- The first migration created admin@test.com with the published password
+-- admin123. Remove the account while it is still exactly as seeded; one that
+-- has been used is kept, and the server refuses to start for as long as it
+-- still accepts that password.
+DELETE FROM users u
+WHERE u.email = 'admin@test.com'
+  AND u.password_hash = '$2b$12$kWuHU0f6jMJTmp50oSesYeOrk6k.aR36RaeSvKc2GBLUBFCVnAHkS'
+  AND NOT EXISTS (SELECT 1 FROM groups g WHERE g.created_by = u.id)
+  AND NOT EXISTS (SELECT 1 FROM expenses e WHERE e.paid_by = u.id)
+  AND NOT EXISTS (SELECT 1 FROM expense_splits s WHERE s.user_id = u.id)
+  AND NOT EXISTS (
+      SELECT 1 FROM payments p WHERE p.from_user_id = u.id OR p.to_user_id = u.id
+  );
@@ -1,8 +1,25 @@
-use crate::errors::{AppResult, AuthError};
-use crate::models::User;
-use chrono::{Duration, Utc};
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use crate::errors::{AppError, AppResult, AuthError, DatabaseError, GroupError};
+use crate::jwtkeys::JwtKeys;
+use crate::models::{PersonalAccessToken, User};
+use actix_web::web;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm as Argon2Algorithm, Argon2, Params, Version};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::errors::ErrorKind;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+/// Marks personal access tokens, so they can be told apart from JWTs in the
+/// `Authorization` header.
+pub const ACCESS_TOKEN_PREFIX: &str = "esp_";
+const TWO_FACTOR_CHALLENGE_TTL_MINUTES: i64 = 5;
+const TWO_FACTOR_PURPOSE: &str = "two_factor";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -11,46 +28,315 @@ pub struct Claims {
     pub username: String,
     pub is_admin: bool,
     pub exp: i64,
+    pub jti: String,
+}
+
+/// Claims carried by a group invitation link. The invitation row itself
+/// decides whether the link is still usable.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvitationClaims {
+    pub invitation_id: Uuid,
+    pub group_id: Uuid,
+    pub exp: i64,
+}
+
+/// Claims of the challenge token `login` hands out when the account has
+/// two-factor authentication enabled. It only proves the password was right
+/// and can't be used as an access token.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorClaims {
+    pub user_id: Uuid,
+    pub email: String,
+    pub purpose: String,
+    pub exp: i64,
+}
+
+/// Cost parameters for Argon2id password hashes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordHashConfig {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashConfig {
+    /// The OWASP-recommended minimum for Argon2id.
+    fn default() -> Self {
+        PasswordHashConfig {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl PasswordHashConfig {
+    fn params(&self) -> AppResult<Params> {
+        Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| AppError::Internal(format!("Invalid Argon2 parameters: {}", e)))
+    }
+}
+
+static PASSWORD_HASH_CONFIG: OnceLock<PasswordHashConfig> = OnceLock::new();
+
+/// Sets the Argon2id parameters new hashes are made with. Called once at
+/// startup; without it the defaults apply.
+pub fn configure_password_hashing(config: PasswordHashConfig) -> AppResult<()> {
+    config.params()?;
+    PASSWORD_HASH_CONFIG
+        .set(config)
+        .map_err(|_| AppError::Internal("Password hashing already configured".to_string()))
+}
+
+fn password_hash_config() -> PasswordHashConfig {
+    *PASSWORD_HASH_CONFIG.get_or_init(PasswordHashConfig::default)
+}
+
+fn argon2() -> AppResult<Argon2<'static>> {
+    Ok(Argon2::new(
+        Argon2Algorithm::Argon2id,
+        Version::V0x13,
+        password_hash_config().params()?,
+    ))
 }
 
 pub fn hash_password(password: &str) -> AppResult<String> {
-    bcrypt::hash(password, bcrypt::DEFAULT_COST)
+    let salt = SaltString::generate(&mut OsRng);
+    argon2()?
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
         .map_err(|e| AuthError::PasswordHashError(e.to_string()).into())
 }
 
+/// Checks a password against a stored hash. Argon2 hashes carry their own
+/// parameters; anything else is treated as a legacy bcrypt hash.
 pub fn verify_password(password: &str, hash: &str) -> AppResult<bool> {
-    bcrypt::verify(password, hash).map_err(|e| AuthError::PasswordHashError(e.to_string()).into())
+    if !hash.starts_with("$argon2") {
+        return bcrypt::verify(password, hash)
+            .map_err(|e| AuthError::PasswordHashError(e.to_string()).into());
+    }
+
+    let parsed =
+        PasswordHash::new(hash).map_err(|e| AuthError::PasswordHashError(e.to_string()))?;
+    match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(AuthError::PasswordHashError(e.to_string()).into()),
+    }
+}
+
+/// Runs `hash_password` on the blocking thread pool. Hashing takes long
+/// enough that request handlers must not do it on an async worker.
+pub async fn hash_password_async(password: &str) -> AppResult<String> {
+    let password = password.to_string();
+    web::block(move || hash_password(&password))
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+}
+
+/// Runs `verify_password` on the blocking thread pool, like
+/// `hash_password_async`.
+pub async fn verify_password_async(password: &str, hash: &str) -> AppResult<bool> {
+    let (password, hash) = (password.to_string(), hash.to_string());
+    web::block(move || verify_password(&password, &hash))
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+}
+
+/// Checks `password` against a throwaway hash made with the current
+/// settings. Logins for unknown emails run it so they take as long as real
+/// ones, and the response time doesn't give away which addresses have
+/// accounts.
+pub async fn verify_dummy_password(password: &str) {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    let password = password.to_string();
+    let _ = web::block(move || {
+        let hash = DUMMY_HASH.get_or_init(|| hash_password("dummy password").unwrap_or_default());
+        verify_password(&password, hash)
+    })
+    .await;
+}
+
+/// Whether a stored hash should be replaced: it is a legacy bcrypt hash, or
+/// an Argon2 hash made with different parameters than the current ones.
+pub fn needs_rehash(hash: &str) -> bool {
+    let Ok(parsed) = PasswordHash::new(hash) else {
+        return true;
+    };
+    if parsed.algorithm != Argon2Algorithm::Argon2id.ident() {
+        return true;
+    }
+
+    let config = password_hash_config();
+    let current = [
+        ("m", config.memory_kib),
+        ("t", config.iterations),
+        ("p", config.parallelism),
+    ];
+    current.iter().any(|(name, value)| {
+        parsed
+            .params
+            .get_decimal(*name)
+            .is_none_or(|stored| stored != *value)
+    })
 }
 
-pub fn create_jwt(user: &User, secret: &str) -> AppResult<String> {
-    let expiration = Utc::now() + Duration::hours(24);
+/// Generates a random opaque token for refresh links and the like.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hashes an opaque token for storage, so a leaked table can't be replayed.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn create_jwt(
+    user: &User,
+    session_id: Uuid,
+    ttl: Duration,
+    keys: &JwtKeys,
+) -> AppResult<String> {
+    let expiration = Utc::now() + ttl;
 
     let claims = Claims {
         user_id: user.id.to_string(),
         email: user.email.clone(),
         username: user.username.clone(),
-        is_admin: user.is_admin.unwrap_or(false),
+        is_admin: user.is_admin,
         exp: expiration.timestamp(),
+        jti: session_id.to_string(),
     };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_ref()),
+    keys.encode(&claims)
+}
+
+/// Decodes an access token and checks that the session it was issued for
+/// hasn't been logged out or revoked since.
+pub async fn verify_jwt(token: &str, keys: &JwtKeys, pool: &PgPool) -> AppResult<Claims> {
+    let claims = decode_jwt(token, keys)?;
+    let session_id = Uuid::from_str(&claims.jti).map_err(|_| AuthError::InvalidToken)?;
+
+    let active = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM sessions WHERE id = $1 AND revoked_at IS NULL)",
     )
-    .map_err(|e| AuthError::JwtError(e.to_string()).into())
+    .bind(session_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    if !active {
+        return Err(AuthError::SessionRevoked.into());
+    }
+    Ok(claims)
 }
 
-pub fn verify_jwt(token: &str, secret: &str) -> AppResult<Claims> {
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_ref()),
-        &Validation::new(Algorithm::HS256),
+/// Looks up an unexpired, unrevoked personal access token and records that
+/// it was used. The owner's details are returned as claims, so handlers can
+/// treat the caller like any other.
+pub async fn verify_access_token(
+    token: &str,
+    pool: &PgPool,
+) -> AppResult<(Claims, PersonalAccessToken)> {
+    let access_token = sqlx::query_as::<_, PersonalAccessToken>(
+        "UPDATE personal_access_tokens SET last_used_at = NOW() 
+         WHERE token_hash = $1 AND revoked_at IS NULL 
+         AND (expires_at IS NULL OR expires_at > NOW()) 
+         RETURNING *",
     )
-    .map(|data| data.claims)
-    .map_err(|e| match e.kind() {
-        jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::TokenExpired.into(),
-        jsonwebtoken::errors::ErrorKind::InvalidToken => AuthError::InvalidToken.into(),
+    .bind(hash_token(token))
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+    .ok_or(AuthError::InvalidToken)?;
+
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(access_token.user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+        .ok_or(AuthError::UserNotFound)?;
+
+    let claims = Claims {
+        user_id: user.id.to_string(),
+        email: user.email,
+        username: user.username,
+        is_admin: user.is_admin,
+        exp: access_token
+            .expires_at
+            .map(|expires_at| expires_at.timestamp())
+            .unwrap_or(i64::MAX),
+        jti: access_token.id.to_string(),
+    };
+    Ok((claims, access_token))
+}
+
+fn decode_jwt(token: &str, keys: &JwtKeys) -> AppResult<Claims> {
+    keys.decode::<Claims>(token).map_err(|e| match e.kind() {
+        ErrorKind::ExpiredSignature => AuthError::TokenExpired.into(),
+        // Other kinds of tokens signed with the same keys, such as 2FA
+        // challenges, fail to deserialize as access token claims. Tokens
+        // signed with a key that has since been retired no longer match any.
+        ErrorKind::InvalidToken
+        | ErrorKind::InvalidSignature
+        | ErrorKind::InvalidAlgorithm
+        | ErrorKind::Base64(_)
+        | ErrorKind::Json(_)
+        | ErrorKind::Utf8(_) => AuthError::InvalidToken.into(),
         _ => AuthError::JwtError(e.to_string()).into(),
     })
 }
+
+pub fn create_invitation_token(
+    invitation_id: Uuid,
+    group_id: Uuid,
+    expires_at: DateTime<Utc>,
+    keys: &JwtKeys,
+) -> AppResult<String> {
+    let claims = InvitationClaims {
+        invitation_id,
+        group_id,
+        exp: expires_at.timestamp(),
+    };
+
+    keys.encode(&claims)
+}
+
+pub fn verify_invitation_token(token: &str, keys: &JwtKeys) -> AppResult<InvitationClaims> {
+    keys.decode::<InvitationClaims>(token)
+        .map_err(|e| match e.kind() {
+            ErrorKind::ExpiredSignature => GroupError::InvitationExpired.into(),
+            _ => GroupError::InvalidInvitation.into(),
+        })
+}
+
+pub fn create_two_factor_token(user: &User, keys: &JwtKeys) -> AppResult<String> {
+    let claims = TwoFactorClaims {
+        user_id: user.id,
+        email: user.email.clone(),
+        purpose: TWO_FACTOR_PURPOSE.to_string(),
+        exp: (Utc::now() + Duration::minutes(TWO_FACTOR_CHALLENGE_TTL_MINUTES)).timestamp(),
+    };
+
+    keys.encode(&claims)
+}
+
+pub fn verify_two_factor_token(token: &str, keys: &JwtKeys) -> AppResult<TwoFactorClaims> {
+    let claims = keys
+        .decode::<TwoFactorClaims>(token)
+        .map_err(|e| -> AppError {
+            match e.kind() {
+                ErrorKind::ExpiredSignature => AuthError::TokenExpired.into(),
+                _ => AuthError::InvalidToken.into(),
+            }
+        })?;
+
+    if claims.purpose != TWO_FACTOR_PURPOSE {
+        return Err(AuthError::InvalidToken.into());
+    }
+    Ok(claims)
+}
@@ -1,14 +1,63 @@
-use sqlx::PgPool;
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::{PgExecutor, PgPool};
 use uuid::Uuid;
 
-use crate::auth::{create_jwt, verify_password};
-use crate::errors::{AppResult, AuthError, DatabaseError, ValidationError};
-use crate::models::{LoginRequest, UserResponse};
+use crate::auth::{
+    ACCESS_TOKEN_PREFIX, TwoFactorClaims, create_jwt, create_two_factor_token, generate_token,
+    hash_password_async, hash_token, needs_rehash, verify_dummy_password, verify_password_async,
+};
+use crate::config::TokenLifetimes;
+use crate::errors::{
+    AppError, AppResult, AuthError, DatabaseError, GroupError, UserError, ValidationError,
+};
+use crate::jwtkeys::JwtKeys;
+use crate::models::{
+    AuthTokens, CreateAccessToken, LoginRequest, OidcCallback, PersonalAccessToken,
+    PersonalAccessTokenResponse, Session, TokenPurpose, TwoFactorEnrollment, UpdateProfile, User,
+    UserResponse, UserTotp,
+};
+use crate::oidc::{OidcClient, OidcIdentity};
+use crate::twofactor;
+
+pub const OIDC_LOGIN_TTL_MINUTES: i64 = 10;
+
+/// Settings for account emails and the login checks that depend on them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountSettings {
+    /// Base URL of the web app, used to build the links in account emails.
+    pub app_url: String,
+    pub require_verified_email: bool,
+}
+
+impl Default for AccountSettings {
+    fn default() -> Self {
+        AccountSettings {
+            app_url: "http://localhost:4200".to_string(),
+            require_verified_email: false,
+        }
+    }
+}
+
+pub enum LoginOutcome {
+    Authenticated(AuthTokens, UserResponse),
+    /// The password was right, but the account has two-factor
+    /// authentication enabled. The challenge token has to be exchanged
+    /// together with a code before any session is started.
+    TwoFactorRequired(String),
+}
 
 pub struct AuthService;
 
 impl AuthService {
-    pub async fn login(pool: &PgPool, request: &LoginRequest) -> AppResult<(String, UserResponse)> {
+    pub async fn login(
+        pool: &PgPool,
+        request: &LoginRequest,
+        keys: &JwtKeys,
+        lifetimes: &TokenLifetimes,
+        require_verified_email: bool,
+    ) -> AppResult<LoginOutcome> {
         let user = sqlx::query_as!(
             crate::models::User,
             "SELECT * FROM users WHERE email = $1",
@@ -16,27 +65,808 @@ impl AuthService {
         )
         .fetch_optional(pool)
         .await
-        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
-        .ok_or(AuthError::InvalidCredentials)?;
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        let Some(user) = user else {
+            verify_dummy_password(&request.password).await;
+            return Err(AuthError::InvalidCredentials.into());
+        };
 
-        let is_valid = verify_password(&request.password, &user.password_hash)?;
+        let is_valid = verify_password_async(&request.password, &user.password_hash).await?;
 
         if !is_valid {
             return Err(AuthError::InvalidCredentials.into());
         }
 
-        let jwt_secret = std::env::var("JWT_SECRET").map_err(|_| AuthError::MissingJwtSecret)?;
+        if needs_rehash(&user.password_hash) {
+            Self::rehash_password(pool, user.id, &request.password).await;
+        }
+
+        Self::finish_login(pool, user, keys, lifetimes, require_verified_email).await
+    }
+
+    /// The checks every login goes through once the user has proven who
+    /// they are, whether by password or through the identity provider. A
+    /// session is started unless a second factor is still due.
+    async fn finish_login(
+        pool: &PgPool,
+        user: User,
+        keys: &JwtKeys,
+        lifetimes: &TokenLifetimes,
+        require_verified_email: bool,
+    ) -> AppResult<LoginOutcome> {
+        if require_verified_email && user.email_verified_at.is_none() {
+            return Err(AuthError::EmailNotVerified.into());
+        }
+
+        if Self::two_factor_enabled(pool, user.id).await? {
+            let challenge_token = create_two_factor_token(&user, keys)?;
+            return Ok(LoginOutcome::TwoFactorRequired(challenge_token));
+        }
+
+        let tokens = Self::start_session(pool, &user, Uuid::new_v4(), keys, lifetimes).await?;
+
+        let user_response = UserResponse {
+            id: user.id,
+            email: user.email,
+            username: user.username,
+            is_admin: user.is_admin,
+            email_verified: user.email_verified_at.is_some(),
+        };
+
+        Ok(LoginOutcome::Authenticated(tokens, user_response))
+    }
+
+    /// Finishes a login that was held back for two-factor authentication,
+    /// once the challenge token has been checked and `code` matches.
+    pub async fn complete_two_factor_login(
+        pool: &PgPool,
+        challenge: &TwoFactorClaims,
+        code: &str,
+        keys: &JwtKeys,
+        lifetimes: &TokenLifetimes,
+    ) -> AppResult<(AuthTokens, UserResponse)> {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(challenge.user_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+            .ok_or(AuthError::UserNotFound)?;
+
+        Self::check_second_factor(&mut tx, user.id, code).await?;
+        let tokens = Self::start_session(&mut *tx, &user, Uuid::new_v4(), keys, lifetimes).await?;
 
-        let token = create_jwt(&user, &jwt_secret)?;
+        tx.commit()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
 
         let user_response = UserResponse {
             id: user.id,
             email: user.email,
             username: user.username,
-            is_admin: user.is_admin.unwrap_or(false),
+            is_admin: user.is_admin,
+            email_verified: user.email_verified_at.is_some(),
+        };
+
+        Ok((tokens, user_response))
+    }
+
+    /// Starts setting up two-factor authentication with a new secret. It
+    /// only takes effect once a code from it is confirmed with
+    /// `enable_two_factor`; starting over replaces an unconfirmed secret.
+    pub async fn begin_two_factor_enrollment(
+        pool: &PgPool,
+        user_id: Uuid,
+    ) -> AppResult<TwoFactorEnrollment> {
+        let email = sqlx::query_scalar::<_, String>("SELECT email FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+            .ok_or(UserError::NotFound)?;
+
+        let secret = twofactor::generate_secret();
+        let provisioning_uri = twofactor::provisioning_uri(&secret, &email)?;
+
+        let stored = sqlx::query(
+            "INSERT INTO user_totp (user_id, secret) VALUES ($1, $2) 
+             ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, last_used_step = NULL 
+             WHERE user_totp.enabled_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(&secret)
+        .execute(pool)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        if stored.rows_affected() == 0 {
+            return Err(AuthError::TwoFactorAlreadyEnabled.into());
+        }
+
+        Ok(TwoFactorEnrollment {
+            secret,
+            provisioning_uri,
+        })
+    }
+
+    /// Confirms enrollment with a code from the authenticator app and
+    /// returns a fresh set of recovery codes. They are only shown this once.
+    pub async fn enable_two_factor(
+        pool: &PgPool,
+        user_id: Uuid,
+        code: &str,
+    ) -> AppResult<Vec<String>> {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        let totp =
+            sqlx::query_as::<_, UserTotp>("SELECT * FROM user_totp WHERE user_id = $1 FOR UPDATE")
+                .bind(user_id)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+                .ok_or(AuthError::TwoFactorNotEnabled)?;
+
+        if totp.enabled_at.is_some() {
+            return Err(AuthError::TwoFactorAlreadyEnabled.into());
+        }
+
+        let step =
+            twofactor::verify_code(&totp.secret, code.trim(), Utc::now().timestamp() as u64)?
+                .ok_or(AuthError::InvalidTwoFactorCode)?;
+
+        sqlx::query(
+            "UPDATE user_totp SET enabled_at = NOW(), last_used_step = $1 WHERE user_id = $2",
+        )
+        .bind(step)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        let recovery_codes = twofactor::generate_recovery_codes();
+        let hashes: Vec<String> = recovery_codes
+            .iter()
+            .map(|code| hash_token(&twofactor::normalize_recovery_code(code)))
+            .collect();
+
+        sqlx::query(
+            "INSERT INTO user_recovery_codes (user_id, code_hash) 
+             SELECT $1, UNNEST($2::VARCHAR[])",
+        )
+        .bind(user_id)
+        .bind(&hashes)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        tx.commit()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        Ok(recovery_codes)
+    }
+
+    /// Turns two-factor authentication off after checking a current code or
+    /// an unused recovery code.
+    pub async fn disable_two_factor(pool: &PgPool, user_id: Uuid, code: &str) -> AppResult<()> {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        Self::check_second_factor(&mut tx, user_id, code).await?;
+
+        sqlx::query("DELETE FROM user_totp WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        tx.commit()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn two_factor_enabled(pool: &PgPool, user_id: Uuid) -> AppResult<bool> {
+        sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM user_totp WHERE user_id = $1 AND enabled_at IS NOT NULL)",
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()).into())
+    }
+
+    /// Accepts either a TOTP code newer than the last one used, or an unused
+    /// recovery code, which is then spent.
+    async fn check_second_factor(
+        conn: &mut sqlx::PgConnection,
+        user_id: Uuid,
+        code: &str,
+    ) -> AppResult<()> {
+        let totp = sqlx::query_as::<_, UserTotp>(
+            "SELECT * FROM user_totp WHERE user_id = $1 AND enabled_at IS NOT NULL FOR UPDATE",
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+        .ok_or(AuthError::TwoFactorNotEnabled)?;
+
+        let step =
+            twofactor::verify_code(&totp.secret, code.trim(), Utc::now().timestamp() as u64)?;
+        if let Some(step) = step {
+            if totp.last_used_step.is_some_and(|last| step <= last) {
+                return Err(AuthError::InvalidTwoFactorCode.into());
+            }
+            sqlx::query("UPDATE user_totp SET last_used_step = $1 WHERE user_id = $2")
+                .bind(step)
+                .bind(user_id)
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+            return Ok(());
+        }
+
+        let recovered = sqlx::query(
+            "UPDATE user_recovery_codes SET used_at = NOW() 
+             WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(hash_token(&twofactor::normalize_recovery_code(code)))
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        if recovered.rows_affected() == 0 {
+            return Err(AuthError::InvalidTwoFactorCode.into());
+        }
+        Ok(())
+    }
+
+    /// Starts a single sign-on login. A fresh state, nonce and PKCE verifier
+    /// are stored for the callback, and the provider URL to send the user to
+    /// is returned along with the state.
+    pub async fn begin_oidc_login(pool: &PgPool, oidc: &OidcClient) -> AppResult<(String, String)> {
+        let state = generate_token();
+        let nonce = generate_token();
+        let code_verifier = generate_token();
+        let authorization_url = oidc
+            .authorization_url(&state, &nonce, &code_verifier)
+            .await?;
+
+        // Logins that were abandoned halfway are cleared out as new ones start
+        sqlx::query("DELETE FROM oidc_login_states WHERE expires_at <= NOW()")
+            .execute(pool)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        sqlx::query(
+            "INSERT INTO oidc_login_states (state_hash, code_verifier, nonce, expires_at) 
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(hash_token(&state))
+        .bind(&code_verifier)
+        .bind(&nonce)
+        .bind(Utc::now() + Duration::minutes(OIDC_LOGIN_TTL_MINUTES))
+        .execute(pool)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        Ok((authorization_url, state))
+    }
+
+    /// Finishes a single sign-on login once the provider has sent the user
+    /// back. The provider only stands in for the password: the email and
+    /// two-factor checks of a password login still apply.
+    pub async fn complete_oidc_login(
+        pool: &PgPool,
+        oidc: &OidcClient,
+        callback: &OidcCallback,
+        keys: &JwtKeys,
+        lifetimes: &TokenLifetimes,
+        require_verified_email: bool,
+    ) -> AppResult<LoginOutcome> {
+        let (code_verifier, nonce) = sqlx::query_as::<_, (String, String)>(
+            "DELETE FROM oidc_login_states WHERE state_hash = $1 AND expires_at > NOW() 
+             RETURNING code_verifier, nonce",
+        )
+        .bind(hash_token(&callback.state))
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+        .ok_or(AuthError::InvalidToken)?;
+
+        let identity = oidc
+            .exchange_code(&callback.code, &code_verifier, &nonce)
+            .await?;
+        let user = Self::find_oidc_user(pool, &identity, oidc.settings.auto_provision).await?;
+
+        Self::finish_login(pool, user, keys, lifetimes, require_verified_email).await
+    }
+
+    /// Finds the user an external identity is linked to. A first login is
+    /// linked to the account with the same email address, but only when both
+    /// the provider and this app have verified that address. Anyone can
+    /// register an account under someone else's address, and linking to it
+    /// would hand the real owner's logins to whoever holds its password.
+    /// Failing that, a new account is created if `auto_provision` allows it.
+    async fn find_oidc_user(
+        pool: &PgPool,
+        identity: &OidcIdentity,
+        auto_provision: bool,
+    ) -> AppResult<User> {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        let linked = sqlx::query_as::<_, User>(
+            "UPDATE user_identities i SET last_login_at = NOW() FROM users u 
+             WHERE i.user_id = u.id AND i.issuer = $1 AND i.subject = $2 RETURNING u.*",
+        )
+        .bind(&identity.issuer)
+        .bind(&identity.subject)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        if let Some(user) = linked {
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+            return Ok(user);
+        }
+
+        let email = identity
+            .email
+            .as_deref()
+            .ok_or(AuthError::OidcAccountNotLinked)?;
+
+        let existing = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+            .bind(email)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        let user = match existing {
+            Some(user) if identity.email_verified && user.email_verified_at.is_some() => user,
+            None if auto_provision => Self::provision_oidc_user(&mut tx, identity, email).await?,
+            _ => return Err(AuthError::OidcAccountNotLinked.into()),
         };
 
-        Ok((token, user_response))
+        sqlx::query(
+            "INSERT INTO user_identities (user_id, issuer, subject, email, last_login_at) 
+             VALUES ($1, $2, $3, $4, NOW())",
+        )
+        .bind(user.id)
+        .bind(&identity.issuer)
+        .bind(&identity.subject)
+        .bind(email)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        tx.commit()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        log::info!(
+            "Linked {} login {} to user {}",
+            identity.issuer,
+            identity.subject,
+            user.id
+        );
+        Ok(user)
+    }
+
+    /// Creates an account for a single sign-on user. It gets a random
+    /// password nobody knows, so it can only be used through the provider
+    /// until the user sets one with a password reset.
+    async fn provision_oidc_user(
+        conn: &mut sqlx::PgConnection,
+        identity: &OidcIdentity,
+        email: &str,
+    ) -> AppResult<User> {
+        let base: String = identity
+            .preferred_username
+            .as_deref()
+            .unwrap_or_else(|| email.split('@').next().unwrap_or(email))
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+            .take(90)
+            .collect();
+        let base = if base.is_empty() {
+            "user".to_string()
+        } else {
+            base
+        };
+
+        let taken =
+            sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM users WHERE username = $1)")
+                .bind(&base)
+                .fetch_one(&mut *conn)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+        let username = if taken {
+            format!("{}-{}", base, &generate_token()[..6])
+        } else {
+            base
+        };
+
+        let password_hash = hash_password_async(&generate_token()).await?;
+
+        sqlx::query_as::<_, User>(
+            "INSERT INTO users (email, username, password_hash, email_verified_at) 
+             VALUES ($1, $2, $3, CASE WHEN $4 THEN NOW() END) RETURNING *",
+        )
+        .bind(email)
+        .bind(&username)
+        .bind(&password_hash)
+        .bind(identity.email_verified)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(map_user_write_error)
+    }
+
+    /// Trades a refresh token for a new access token, rotating the refresh
+    /// token as it goes. Presenting a token that was already rotated means it
+    /// leaked, so the whole family of sessions descending from that login is
+    /// revoked.
+    pub async fn refresh(
+        pool: &PgPool,
+        refresh_token: &str,
+        keys: &JwtKeys,
+        lifetimes: &TokenLifetimes,
+    ) -> AppResult<AuthTokens> {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        let session = sqlx::query_as::<_, Session>(
+            "SELECT * FROM sessions WHERE refresh_token_hash = $1 FOR UPDATE",
+        )
+        .bind(hash_token(refresh_token))
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+        .ok_or(AuthError::InvalidToken)?;
+
+        if session.revoked_at.is_some() {
+            return Err(AuthError::SessionRevoked.into());
+        }
+
+        if session.used_at.is_some() {
+            log::warn!(
+                "Refresh token reuse detected, revoking session family {}",
+                session.family_id
+            );
+            Self::revoke_family(&mut *tx, session.family_id).await?;
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+            return Err(AuthError::SessionRevoked.into());
+        }
+
+        if session.expires_at <= Utc::now() {
+            return Err(AuthError::TokenExpired.into());
+        }
+
+        sqlx::query("UPDATE sessions SET used_at = NOW() WHERE id = $1")
+            .bind(session.id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(session.user_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+            .ok_or(AuthError::UserNotFound)?;
+
+        let tokens =
+            Self::start_session(&mut *tx, &user, session.family_id, keys, lifetimes).await?;
+
+        tx.commit()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        Ok(tokens)
+    }
+
+    /// Ends the login an access token belongs to, including every refresh
+    /// token rotated from it.
+    pub async fn logout(pool: &PgPool, session_id: Uuid) -> AppResult<()> {
+        let family_id =
+            sqlx::query_scalar::<_, Uuid>("SELECT family_id FROM sessions WHERE id = $1")
+                .bind(session_id)
+                .fetch_optional(pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+                .ok_or(AuthError::InvalidToken)?;
+
+        Self::revoke_family(pool, family_id).await
+    }
+
+    /// Upgrades a stored hash to the current Argon2id settings after a
+    /// successful login. Failures are only logged, since the old hash still
+    /// works and the next login will try again.
+    async fn rehash_password(pool: &PgPool, user_id: Uuid, password: &str) {
+        let password_hash = match hash_password_async(password).await {
+            Ok(hash) => hash,
+            Err(e) => {
+                log::error!("Failed to rehash password for user {}: {}", user_id, e);
+                return;
+            }
+        };
+
+        if let Err(e) = sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2")
+            .bind(&password_hash)
+            .bind(user_id)
+            .execute(pool)
+            .await
+        {
+            log::error!(
+                "Failed to store rehashed password for user {}: {}",
+                user_id,
+                e
+            );
+        }
+    }
+
+    /// Creates a password reset token for the account registered under
+    /// `email`, if there is one. Earlier reset tokens stop working.
+    pub async fn request_password_reset(
+        pool: &PgPool,
+        email: &str,
+        ttl: Duration,
+    ) -> AppResult<Option<(User, String)>> {
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+            .bind(email)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        let Some(user) = user else {
+            return Ok(None);
+        };
+
+        let token = Self::issue_user_token(pool, user.id, TokenPurpose::PasswordReset, ttl).await?;
+        Ok(Some((user, token)))
+    }
+
+    /// Sets a new password from a reset token, logs the account out
+    /// everywhere and revokes its access tokens.
+    pub async fn reset_password(pool: &PgPool, token: &str, new_password: &str) -> AppResult<()> {
+        let password_hash = hash_password_async(new_password).await?;
+
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        let user_id =
+            Self::consume_user_token(&mut *tx, token, TokenPurpose::PasswordReset).await?;
+
+        sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2")
+            .bind(&password_hash)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        Self::revoke_user_sessions(&mut *tx, user_id).await?;
+        Self::revoke_access_tokens(&mut *tx, user_id).await?;
+
+        tx.commit()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+        Ok(())
+    }
+
+    pub async fn request_email_verification(
+        pool: &PgPool,
+        user_id: Uuid,
+        ttl: Duration,
+    ) -> AppResult<String> {
+        Self::issue_user_token(pool, user_id, TokenPurpose::EmailVerification, ttl).await
+    }
+
+    /// Issues a new verification token for the unverified account with this
+    /// email, if there is one.
+    pub async fn resend_email_verification(
+        pool: &PgPool,
+        email: &str,
+        ttl: Duration,
+    ) -> AppResult<Option<(User, String)>> {
+        let user = sqlx::query_as::<_, User>(
+            "SELECT * FROM users WHERE email = $1 AND email_verified_at IS NULL",
+        )
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        let Some(user) = user else {
+            return Ok(None);
+        };
+
+        let token =
+            Self::issue_user_token(pool, user.id, TokenPurpose::EmailVerification, ttl).await?;
+        Ok(Some((user, token)))
+    }
+
+    pub async fn verify_email(pool: &PgPool, token: &str) -> AppResult<()> {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        let user_id =
+            Self::consume_user_token(&mut *tx, token, TokenPurpose::EmailVerification).await?;
+
+        sqlx::query(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        tx.commit()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn issue_user_token(
+        pool: &PgPool,
+        user_id: Uuid,
+        purpose: TokenPurpose,
+        ttl: Duration,
+    ) -> AppResult<String> {
+        let token = generate_token();
+
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        sqlx::query(
+            "UPDATE user_tokens SET used_at = NOW() 
+             WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(purpose)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        sqlx::query(
+            "INSERT INTO user_tokens (user_id, purpose, token_hash, expires_at) 
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(user_id)
+        .bind(purpose)
+        .bind(hash_token(&token))
+        .bind(Utc::now() + ttl)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        tx.commit()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+        Ok(token)
+    }
+
+    /// Marks a mailed token as used and returns the user it belongs to. The
+    /// check and the update are a single statement, so a token can only ever
+    /// be redeemed once.
+    async fn consume_user_token(
+        executor: impl PgExecutor<'_>,
+        token: &str,
+        purpose: TokenPurpose,
+    ) -> AppResult<Uuid> {
+        sqlx::query_scalar::<_, Uuid>(
+            "UPDATE user_tokens SET used_at = NOW() 
+             WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > NOW() 
+             RETURNING user_id",
+        )
+        .bind(hash_token(token))
+        .bind(purpose)
+        .fetch_optional(executor)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+        .ok_or(AuthError::InvalidToken.into())
+    }
+
+    async fn revoke_user_sessions(executor: impl PgExecutor<'_>, user_id: Uuid) -> AppResult<()> {
+        sqlx::query(
+            "UPDATE sessions SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(executor)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Revokes every personal access token a user still has, so a password
+    /// change also cuts off tokens that may have leaked along with it.
+    async fn revoke_access_tokens(executor: impl PgExecutor<'_>, user_id: Uuid) -> AppResult<()> {
+        sqlx::query(
+            "UPDATE personal_access_tokens SET revoked_at = NOW() 
+             WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(executor)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn start_session(
+        executor: impl PgExecutor<'_>,
+        user: &User,
+        family_id: Uuid,
+        keys: &JwtKeys,
+        lifetimes: &TokenLifetimes,
+    ) -> AppResult<AuthTokens> {
+        let refresh_token = generate_token();
+        let expires_at = Utc::now() + lifetimes.refresh_token();
+
+        let session_id = sqlx::query_scalar::<_, Uuid>(
+            "INSERT INTO sessions (user_id, family_id, refresh_token_hash, expires_at) 
+             VALUES ($1, $2, $3, $4) RETURNING id",
+        )
+        .bind(user.id)
+        .bind(family_id)
+        .bind(hash_token(&refresh_token))
+        .bind(expires_at)
+        .fetch_one(executor)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        Ok(AuthTokens {
+            token: create_jwt(user, session_id, lifetimes.access_token(), keys)?,
+            refresh_token,
+            expires_in: lifetimes.access_token().num_seconds(),
+        })
+    }
+
+    async fn revoke_family(executor: impl PgExecutor<'_>, family_id: Uuid) -> AppResult<()> {
+        sqlx::query(
+            "UPDATE sessions SET revoked_at = NOW() WHERE family_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(family_id)
+        .execute(executor)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+        Ok(())
     }
 
     pub async fn register(
@@ -59,7 +889,7 @@ impl AuthService {
             .into());
         }
 
-        let password_hash = crate::auth::hash_password(password)?;
+        let password_hash = hash_password_async(password).await?;
 
         let user = sqlx::query_as!(
             crate::models::User,
@@ -70,29 +900,195 @@ impl AuthService {
         )
         .fetch_one(pool)
         .await
-        .map_err(|e| -> crate::errors::AppError {
-            match e {
-                sqlx::Error::Database(db_err) if db_err.constraint().is_some() => {
-                    if db_err.constraint().unwrap().contains("email") {
-                        crate::errors::UserError::EmailAlreadyExists.into()
-                    } else if db_err.constraint().unwrap().contains("username") {
-                        crate::errors::UserError::UsernameAlreadyExists.into()
-                    } else {
-                        DatabaseError::ConstraintViolation(db_err.to_string()).into()
-                    }
-                }
-                _ => DatabaseError::QueryFailed(e.to_string()).into(),
-            }
-        })?;
+        .map_err(map_user_write_error)?;
 
         Ok(UserResponse {
             id: user.id,
             email: user.email,
             username: user.username,
-            is_admin: user.is_admin.unwrap_or(false),
+            is_admin: user.is_admin,
+            email_verified: user.email_verified_at.is_some(),
         })
     }
 
+    /// Changes a user's username and/or email. A new email address has to be
+    /// verified again; the returned flag says whether that is the case.
+    pub async fn update_profile(
+        pool: &PgPool,
+        user_id: Uuid,
+        request: &UpdateProfile,
+    ) -> AppResult<(UserResponse, bool)> {
+        let current = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+            .ok_or(crate::errors::UserError::NotFound)?;
+
+        let email_changed = request
+            .email
+            .as_deref()
+            .is_some_and(|email| email != current.email);
+
+        let user = sqlx::query_as::<_, User>(
+            "UPDATE users SET username = COALESCE($1, username), email = COALESCE($2, email), 
+             email_verified_at = CASE WHEN $3 THEN NULL ELSE email_verified_at END 
+             WHERE id = $4 RETURNING *",
+        )
+        .bind(&request.username)
+        .bind(&request.email)
+        .bind(email_changed)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .map_err(map_user_write_error)?;
+
+        Ok((
+            UserResponse {
+                id: user.id,
+                email: user.email,
+                username: user.username,
+                is_admin: user.is_admin,
+                email_verified: user.email_verified_at.is_some(),
+            },
+            email_changed,
+        ))
+    }
+
+    /// Replaces a user's password after checking the current one. Every
+    /// existing session and access token is revoked, and a fresh session is
+    /// started for the caller.
+    pub async fn change_password(
+        pool: &PgPool,
+        user_id: Uuid,
+        current_password: &str,
+        new_password: &str,
+        keys: &JwtKeys,
+        lifetimes: &TokenLifetimes,
+    ) -> AppResult<AuthTokens> {
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+            .ok_or(crate::errors::UserError::NotFound)?;
+
+        if !verify_password_async(current_password, &user.password_hash).await? {
+            return Err(AuthError::InvalidCredentials.into());
+        }
+
+        let password_hash = hash_password_async(new_password).await?;
+
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2")
+            .bind(&password_hash)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        Self::revoke_user_sessions(&mut *tx, user_id).await?;
+        Self::revoke_access_tokens(&mut *tx, user_id).await?;
+        let tokens = Self::start_session(&mut *tx, &user, Uuid::new_v4(), keys, lifetimes).await?;
+
+        tx.commit()
+            .await
+            .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+        Ok(tokens)
+    }
+
+    /// Creates a personal access token. Tokens limited to some groups can
+    /// only name groups the user belongs to.
+    pub async fn create_access_token(
+        pool: &PgPool,
+        user_id: Uuid,
+        request: &CreateAccessToken,
+    ) -> AppResult<PersonalAccessTokenResponse> {
+        if let Some(group_ids) = &request.group_ids {
+            let memberships = sqlx::query_scalar::<_, i64>(
+                "SELECT COUNT(DISTINCT group_id) FROM group_members 
+                 WHERE user_id = $1 AND group_id = ANY($2)",
+            )
+            .bind(user_id)
+            .bind(group_ids)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+            let mut distinct = group_ids.clone();
+            distinct.sort();
+            distinct.dedup();
+            if memberships != distinct.len() as i64 {
+                return Err(GroupError::NotAMember.into());
+            }
+        }
+
+        let token = format!("{}{}", ACCESS_TOKEN_PREFIX, generate_token());
+        let expires_at = request
+            .expires_in_days
+            .map(|days| Utc::now() + Duration::days(days));
+
+        let access_token = sqlx::query_as::<_, PersonalAccessToken>(
+            "INSERT INTO personal_access_tokens 
+             (user_id, name, token_prefix, token_hash, scope, group_ids, expires_at) 
+             VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+        )
+        .bind(user_id)
+        .bind(request.name.trim())
+        .bind(&token[..ACCESS_TOKEN_PREFIX.len() + 8])
+        .bind(hash_token(&token))
+        .bind(request.scope)
+        .bind(&request.group_ids)
+        .bind(expires_at)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        Ok(PersonalAccessTokenResponse {
+            access_token,
+            token,
+        })
+    }
+
+    pub async fn get_access_tokens(
+        pool: &PgPool,
+        user_id: Uuid,
+    ) -> AppResult<Vec<PersonalAccessToken>> {
+        sqlx::query_as::<_, PersonalAccessToken>(
+            "SELECT * FROM personal_access_tokens WHERE user_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()).into())
+    }
+
+    pub async fn revoke_access_token(
+        pool: &PgPool,
+        user_id: Uuid,
+        token_id: Uuid,
+    ) -> AppResult<()> {
+        let result = sqlx::query(
+            "UPDATE personal_access_tokens SET revoked_at = COALESCE(revoked_at, NOW()) 
+             WHERE id = $1 AND user_id = $2",
+        )
+        .bind(token_id)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::AccessTokenNotFound.into());
+        }
+        Ok(())
+    }
+
     pub async fn get_user_by_id(pool: &PgPool, user_id: Uuid) -> AppResult<UserResponse> {
         let user = sqlx::query_as!(
             crate::models::User,
@@ -108,7 +1104,25 @@ impl AuthService {
             id: user.id,
             email: user.email,
             username: user.username,
-            is_admin: user.is_admin.unwrap_or(false),
+            is_admin: user.is_admin,
+            email_verified: user.email_verified_at.is_some(),
         })
     }
 }
+
+/// Turns a failed insert or update on `users` into the matching error,
+/// telling apart clashes on the email and username unique constraints.
+pub fn map_user_write_error(e: sqlx::Error) -> AppError {
+    match e {
+        sqlx::Error::Database(db_err) if db_err.constraint().is_some() => {
+            if db_err.constraint().unwrap().contains("email") {
+                UserError::EmailAlreadyExists.into()
+            } else if db_err.constraint().unwrap().contains("username") {
+                UserError::UsernameAlreadyExists.into()
+            } else {
+                DatabaseError::ConstraintViolation(db_err.to_string()).into()
+            }
+        }
+        _ => DatabaseError::QueryFailed(e.to_string()).into(),
+    }
+}
@@ -0,0 +1,146 @@
+use sqlx::PgPool;
+
+use crate::auth::{hash_password, verify_password};
+use crate::authservice::map_user_write_error;
+use crate::config::AdminConfig;
+use crate::errors::{AppError, AppResult, DatabaseError};
+
+/// Accounts that shipped with a published password, such as the
+/// `admin@test.com` / `admin123` account the first migration creates.
+/// Anyone can log in to one for as long as it still accepts that password,
+/// including after a login has upgraded the stored hash.
+const DEFAULT_CREDENTIALS: &[(&str, &str)] = &[("admin@test.com", "admin123")];
+
+/// The hashes those passwords shipped with, which are just as open under any
+/// other address.
+const DEFAULT_PASSWORD_HASHES: &[&str] =
+    &["$2b$12$kWuHU0f6jMJTmp50oSesYeOrk6k.aR36RaeSvKc2GBLUBFCVnAHkS"];
+
+/// Fails if any account still accepts a default password.
+pub async fn check_default_credentials(pool: &PgPool) -> AppResult<()> {
+    let default_emails: Vec<&str> = DEFAULT_CREDENTIALS
+        .iter()
+        .map(|(email, _)| *email)
+        .collect();
+    let accounts = sqlx::query_as::<_, (String, String)>(
+        "SELECT email, password_hash FROM users 
+         WHERE email = ANY($1) OR password_hash = ANY($2) ORDER BY email",
+    )
+    .bind(&default_emails)
+    .bind(DEFAULT_PASSWORD_HASHES)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    let emails: Vec<String> = accounts
+        .into_iter()
+        .filter(|(email, hash)| {
+            DEFAULT_PASSWORD_HASHES.contains(&hash.as_str())
+                || DEFAULT_CREDENTIALS.iter().any(|(default_email, password)| {
+                    // A hash that can't be read can't be logged in with either
+                    default_email == email && verify_password(password, hash).unwrap_or(false)
+                })
+        })
+        .map(|(email, _)| email)
+        .collect();
+
+    if emails.is_empty() {
+        return Ok(());
+    }
+    Err(AppError::Internal(format!(
+        "Refusing to start: {} still has a default password. Change the password or remove the account first",
+        emails.join(", ")
+    )))
+}
+
+/// Sets up the initial admin from configuration when no account has admin
+/// rights yet. Does nothing once there is an admin, so changing the settings
+/// later has no effect.
+pub async fn bootstrap_admin(pool: &PgPool, config: &AdminConfig) -> AppResult<()> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    // Serializes instances starting at the same time, so only one of them
+    // creates the admin
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('bootstrap_admin'))")
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    let admin_exists =
+        sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM users WHERE is_admin)")
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    if admin_exists {
+        return Ok(());
+    }
+
+    let Some(email) = &config.email else {
+        log::warn!("No admin account exists; set ADMIN_EMAIL to create one");
+        return Ok(());
+    };
+
+    // Only an account that has proven it owns the address is promoted, so
+    // nobody can claim admin rights by registering it first
+    let existing = sqlx::query_scalar::<_, bool>(
+        "SELECT email_verified_at IS NOT NULL FROM users WHERE email = $1",
+    )
+    .bind(email)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    let promoted = match existing {
+        Some(false) => {
+            return Err(AppError::Internal(format!(
+                "Refusing to start: the account {} has not verified its email address, so it can't be made admin. Verify or remove it first",
+                email
+            )));
+        }
+        Some(true) => {
+            sqlx::query("UPDATE users SET is_admin = TRUE WHERE email = $1")
+                .bind(email)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+            true
+        }
+        None => false,
+    };
+
+    if !promoted {
+        let password = config.password.as_deref().ok_or_else(|| {
+            AppError::Internal(format!(
+                "ADMIN_PASSWORD must be set to create the admin account {}",
+                email
+            ))
+        })?;
+        let username = config.username.as_deref().unwrap_or("admin");
+
+        // The address comes from the operator, so it counts as verified
+        sqlx::query(
+            "INSERT INTO users (email, username, password_hash, is_admin, email_verified_at)
+             VALUES ($1, $2, $3, TRUE, NOW())",
+        )
+        .bind(email)
+        .bind(username)
+        .bind(hash_password(password)?)
+        .execute(&mut *tx)
+        .await
+        .map_err(map_user_write_error)?;
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    if promoted {
+        log::info!("Granted admin rights to {}", email);
+    } else {
+        log::info!("Created admin account {}", email);
+    }
+    Ok(())
+}
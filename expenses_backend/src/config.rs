@@ -0,0 +1,524 @@
+use chrono::Duration;
+use reqwest::Url;
+use serde::Deserialize;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::auth::PasswordHashConfig;
+use crate::authservice::AccountSettings;
+use crate::errors::{AppError, AppResult};
+use crate::oidc::OidcSettings;
+use crate::ratelimit::ThrottleConfig;
+
+/// Everything the server is configured with, loaded and checked once at
+/// startup.
+///
+/// Values come from the TOML file named by `CONFIG_FILE`, if set, with
+/// environment variables taking precedence over it. Anything left out falls
+/// back to a default suitable for local development, except the database URL
+/// and the JWT signing material, which have to be given.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub cors: CorsConfig,
+    pub tokens: TokenLifetimes,
+    pub log: LogConfig,
+    pub jwt: JwtConfig,
+    pub accounts: AccountSettings,
+    pub mail: MailConfig,
+    pub password_hashing: PasswordHashConfig,
+    pub throttle: ThrottleConfig,
+    pub oidc: OidcSettings,
+    pub admin: AdminConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// `host:port` to listen on.
+    pub bind_address: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: "0.0.0.0:8080".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    /// How long a request waits for a free connection before failing.
+    pub acquire_timeout_secs: u64,
+    /// Idle connections above `min_connections` are closed after this long.
+    pub idle_timeout_secs: Option<u64>,
+    /// Applies pending migrations from `migrations/` at startup. Turn off
+    /// when the schema is managed separately.
+    pub run_migrations: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: String::new(),
+            max_connections: 5,
+            min_connections: 0,
+            acquire_timeout_secs: 30,
+            idle_timeout_secs: Some(600),
+            run_migrations: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins browsers may call the API from, such as
+    /// `https://expenses.example.com`. `*` allows any origin. Left empty,
+    /// only the web app at `accounts.app_url` is allowed.
+    pub allowed_origins: Vec<String>,
+}
+
+/// How long the tokens the server hands out stay valid.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenLifetimes {
+    /// Clients renew access tokens with their refresh token rather than
+    /// logging in again.
+    pub access_token_minutes: i64,
+    pub refresh_token_days: i64,
+    pub password_reset_minutes: i64,
+    pub email_verification_hours: i64,
+}
+
+impl Default for TokenLifetimes {
+    fn default() -> Self {
+        TokenLifetimes {
+            access_token_minutes: 15,
+            refresh_token_days: 30,
+            password_reset_minutes: 60,
+            email_verification_hours: 48,
+        }
+    }
+}
+
+impl TokenLifetimes {
+    pub fn access_token(&self) -> Duration {
+        Duration::minutes(self.access_token_minutes)
+    }
+
+    pub fn refresh_token(&self) -> Duration {
+        Duration::days(self.refresh_token_days)
+    }
+
+    pub fn password_reset(&self) -> Duration {
+        Duration::minutes(self.password_reset_minutes)
+    }
+
+    pub fn email_verification(&self) -> Duration {
+        Duration::hours(self.email_verification_hours)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// env_logger's human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Log levels are still taken from `RUST_LOG`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+}
+
+/// Where the token signing keys come from. With `signing_key` set, tokens
+/// are signed with that RSA or Ed25519 private key and `verification_keys`
+/// lists retired public keys, as `path` or `kid=path`, that are still
+/// accepted. A `secret` set alongside keeps HS256 tokens working during the
+/// switch. Without a signing key, `secret` signs everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtConfig {
+    pub secret: Option<String>,
+    pub signing_key: Option<PathBuf>,
+    pub key_id: Option<String>,
+    pub verification_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    /// Only logs messages, optionally appending them to `outbox`.
+    #[default]
+    Log,
+    Smtp,
+}
+
+impl FromStr for MailTransport {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(MailTransport::Log),
+            "smtp" => Ok(MailTransport::Smtp),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    pub transport: MailTransport,
+    pub outbox: Option<PathBuf>,
+    pub from: Option<String>,
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            transport: MailTransport::Log,
+            outbox: None,
+            from: None,
+            smtp_host: None,
+            smtp_port: 587,
+            smtp_username: None,
+            smtp_password: None,
+        }
+    }
+}
+
+/// The initial administrator, set up at startup while no account has admin
+/// rights. An existing account with `email` is promoted if it has verified
+/// the address; otherwise a new one is created with `username` and
+/// `password`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub email: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl AppConfig {
+    /// Reads the configuration file and environment and checks the result.
+    pub fn load() -> AppResult<Self> {
+        let mut config = match env_value("CONFIG_FILE") {
+            Some(path) => Self::from_file(Path::new(&path))?,
+            None => AppConfig::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> AppResult<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            AppError::Internal(format!(
+                "Failed to read config file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        toml::from_str(&contents).map_err(|e| {
+            AppError::Internal(format!("Invalid config file {}: {}", path.display(), e))
+        })
+    }
+
+    fn apply_env(&mut self) -> AppResult<()> {
+        override_string(&mut self.server.bind_address, "BIND_ADDRESS");
+
+        override_string(&mut self.database.url, "DATABASE_URL");
+        override_parsed(
+            &mut self.database.max_connections,
+            "DATABASE_MAX_CONNECTIONS",
+        )?;
+        override_parsed(
+            &mut self.database.min_connections,
+            "DATABASE_MIN_CONNECTIONS",
+        )?;
+        override_parsed(
+            &mut self.database.acquire_timeout_secs,
+            "DATABASE_ACQUIRE_TIMEOUT_SECS",
+        )?;
+        override_optional(
+            &mut self.database.idle_timeout_secs,
+            "DATABASE_IDLE_TIMEOUT_SECS",
+        )?;
+        override_bool(&mut self.database.run_migrations, "DATABASE_RUN_MIGRATIONS")?;
+
+        override_list(&mut self.cors.allowed_origins, "CORS_ALLOWED_ORIGINS");
+
+        override_parsed(
+            &mut self.tokens.access_token_minutes,
+            "ACCESS_TOKEN_TTL_MINUTES",
+        )?;
+        override_parsed(
+            &mut self.tokens.refresh_token_days,
+            "REFRESH_TOKEN_TTL_DAYS",
+        )?;
+        override_parsed(
+            &mut self.tokens.password_reset_minutes,
+            "PASSWORD_RESET_TTL_MINUTES",
+        )?;
+        override_parsed(
+            &mut self.tokens.email_verification_hours,
+            "EMAIL_VERIFICATION_TTL_HOURS",
+        )?;
+
+        override_parsed(&mut self.log.format, "LOG_FORMAT")?;
+
+        override_optional(&mut self.jwt.secret, "JWT_SECRET")?;
+        override_optional(&mut self.jwt.signing_key, "JWT_SIGNING_KEY")?;
+        override_optional(&mut self.jwt.key_id, "JWT_KEY_ID")?;
+        override_list(&mut self.jwt.verification_keys, "JWT_VERIFICATION_KEYS");
+
+        override_string(&mut self.accounts.app_url, "APP_URL");
+        override_bool(
+            &mut self.accounts.require_verified_email,
+            "REQUIRE_VERIFIED_EMAIL",
+        )?;
+
+        override_parsed(&mut self.mail.transport, "MAIL_TRANSPORT")?;
+        override_optional(&mut self.mail.outbox, "MAIL_OUTBOX")?;
+        override_optional(&mut self.mail.from, "MAIL_FROM")?;
+        override_optional(&mut self.mail.smtp_host, "SMTP_HOST")?;
+        override_parsed(&mut self.mail.smtp_port, "SMTP_PORT")?;
+        override_optional(&mut self.mail.smtp_username, "SMTP_USERNAME")?;
+        override_optional(&mut self.mail.smtp_password, "SMTP_PASSWORD")?;
+
+        override_parsed(&mut self.password_hashing.memory_kib, "ARGON2_MEMORY_KIB")?;
+        override_parsed(&mut self.password_hashing.iterations, "ARGON2_ITERATIONS")?;
+        override_parsed(&mut self.password_hashing.parallelism, "ARGON2_PARALLELISM")?;
+
+        override_parsed(&mut self.throttle.ip_limit, "THROTTLE_IP_LIMIT")?;
+        override_parsed(&mut self.throttle.ip_window_secs, "THROTTLE_IP_WINDOW_SECS")?;
+        override_parsed(&mut self.throttle.max_failures, "THROTTLE_MAX_FAILURES")?;
+        override_parsed(
+            &mut self.throttle.failure_window_minutes,
+            "THROTTLE_FAILURE_WINDOW_MINUTES",
+        )?;
+        override_parsed(
+            &mut self.throttle.lockout_minutes,
+            "THROTTLE_LOCKOUT_MINUTES",
+        )?;
+
+        override_string(&mut self.oidc.issuer_url, "OIDC_ISSUER_URL");
+        override_string(&mut self.oidc.client_id, "OIDC_CLIENT_ID");
+        override_optional(&mut self.oidc.client_secret, "OIDC_CLIENT_SECRET")?;
+        override_string(&mut self.oidc.redirect_url, "OIDC_REDIRECT_URL");
+        override_string(&mut self.oidc.scopes, "OIDC_SCOPES");
+        override_bool(&mut self.oidc.auto_provision, "OIDC_AUTO_PROVISION")?;
+
+        override_optional(&mut self.admin.email, "ADMIN_EMAIL")?;
+        override_optional(&mut self.admin.username, "ADMIN_USERNAME")?;
+        override_optional(&mut self.admin.password, "ADMIN_PASSWORD")?;
+        Ok(())
+    }
+
+    fn validate(&mut self) -> AppResult<()> {
+        if self
+            .server
+            .bind_address
+            .to_socket_addrs()
+            .map_or(true, |mut addrs| addrs.next().is_none())
+        {
+            return Err(invalid(format!(
+                "server.bind_address {} is not a valid host:port",
+                self.server.bind_address
+            )));
+        }
+
+        let database = &self.database;
+        if database.url.is_empty() {
+            return Err(invalid("DATABASE_URL must be set"));
+        }
+        if database.max_connections == 0 {
+            return Err(invalid("database.max_connections must be at least 1"));
+        }
+        if database.min_connections > database.max_connections {
+            return Err(invalid(
+                "database.min_connections can't exceed database.max_connections",
+            ));
+        }
+        if database.acquire_timeout_secs == 0 || database.idle_timeout_secs == Some(0) {
+            return Err(invalid("database timeouts must be at least 1 second"));
+        }
+
+        let app_url = Url::parse(&self.accounts.app_url)
+            .map_err(|e| invalid(format!("accounts.app_url is not a valid URL: {}", e)))?;
+        self.accounts.app_url = self.accounts.app_url.trim_end_matches('/').to_string();
+
+        if self.cors.allowed_origins.is_empty() {
+            self.cors.allowed_origins = vec![app_url.origin().ascii_serialization()];
+        }
+        for origin in &self.cors.allowed_origins {
+            let valid = origin == "*"
+                || Url::parse(origin)
+                    .is_ok_and(|url| url.origin().ascii_serialization() == *origin);
+            if !valid {
+                return Err(invalid(format!(
+                    "CORS origin {} must be * or scheme://host[:port] without a trailing slash",
+                    origin
+                )));
+            }
+        }
+
+        let tokens = &self.tokens;
+        if [
+            tokens.access_token_minutes,
+            tokens.refresh_token_days,
+            tokens.password_reset_minutes,
+            tokens.email_verification_hours,
+        ]
+        .iter()
+        .any(|lifetime| *lifetime < 1)
+        {
+            return Err(invalid("token lifetimes must be positive"));
+        }
+        if tokens.access_token() > tokens.refresh_token() {
+            return Err(invalid(
+                "access tokens can't outlive the refresh tokens they are renewed with",
+            ));
+        }
+
+        if self.jwt.secret.is_none() && self.jwt.signing_key.is_none() {
+            return Err(invalid("JWT_SECRET or JWT_SIGNING_KEY must be set"));
+        }
+
+        if self.mail.transport == MailTransport::Smtp {
+            if self.mail.smtp_host.is_none() {
+                return Err(invalid("SMTP_HOST must be set"));
+            }
+            if self.mail.from.is_none() {
+                return Err(invalid("MAIL_FROM must be set"));
+            }
+        }
+
+        let throttle = &self.throttle;
+        if throttle.ip_limit == 0 || throttle.max_failures == 0 {
+            return Err(invalid("throttle limits must be at least 1"));
+        }
+        if [
+            throttle.ip_window_secs,
+            throttle.failure_window_minutes,
+            throttle.lockout_minutes,
+        ]
+        .contains(&0)
+        {
+            return Err(invalid("throttle windows and lockout must be positive"));
+        }
+
+        if self.oidc.enabled() {
+            self.oidc.issuer_url = self.oidc.issuer_url.trim_end_matches('/').to_string();
+            if self.oidc.client_id.is_empty() {
+                return Err(invalid("OIDC_CLIENT_ID must be set for OIDC"));
+            }
+            if self.oidc.redirect_url.is_empty() {
+                return Err(invalid("OIDC_REDIRECT_URL must be set for OIDC"));
+            }
+        }
+
+        if let Some(email) = &self.admin.email {
+            if !email.contains('@') {
+                return Err(invalid("ADMIN_EMAIL must be an email address"));
+            }
+            if self.admin.password.as_ref().is_some_and(|p| p.len() < 8) {
+                return Err(invalid("ADMIN_PASSWORD must be at least 8 characters"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: impl Into<String>) -> AppError {
+    AppError::Internal(format!("Invalid configuration: {}", message.into()))
+}
+
+/// The value of an environment variable. Empty values count as unset, as
+/// `.env` files often leave placeholders blank.
+fn env_value(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+fn override_string(target: &mut String, name: &str) {
+    if let Some(value) = env_value(name) {
+        *target = value;
+    }
+}
+
+fn override_parsed<T: FromStr>(target: &mut T, name: &str) -> AppResult<()> {
+    if let Some(value) = env_value(name) {
+        *target = parse_env(name, &value)?;
+    }
+    Ok(())
+}
+
+fn override_optional<T: FromStr>(target: &mut Option<T>, name: &str) -> AppResult<()> {
+    if let Some(value) = env_value(name) {
+        *target = Some(parse_env(name, &value)?);
+    }
+    Ok(())
+}
+
+fn override_bool(target: &mut bool, name: &str) -> AppResult<()> {
+    if let Some(value) = env_value(name) {
+        *target = match value.as_str() {
+            "true" | "1" => true,
+            "false" | "0" => false,
+            _ => return Err(invalid(format!("{} must be true or false", name))),
+        };
+    }
+    Ok(())
+}
+
+/// Comma-separated lists, such as `CORS_ALLOWED_ORIGINS`.
+fn override_list(target: &mut Vec<String>, name: &str) {
+    if let Some(value) = env_value(name) {
+        *target = value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+}
+
+fn parse_env<T: FromStr>(name: &str, value: &str) -> AppResult<T> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid(format!("{} has an invalid value: {}", name, value)))
+}
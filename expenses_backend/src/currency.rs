@@ -0,0 +1,237 @@
+use chrono::NaiveDate;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::str::FromStr;
+
+use crate::errors::{AppResult, ExpenseError, ValidationError};
+use crate::models::{CreateExchangeRate, ExchangeRate};
+
+pub const DEFAULT_CURRENCY: &str = "INR";
+
+pub fn validate_currency_code(code: &str) -> AppResult<()> {
+    if code.len() != 3 || !code.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(ValidationError::InvalidFormat(
+            "ISO 4217 currency code such as USD".to_string(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Rounds a converted amount to the cent, halves away from zero.
+pub fn convert(amount: Decimal, rate: Decimal) -> Decimal {
+    (amount * rate).round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+}
+
+/// The exchange rates needed to bring amounts into one group's currency.
+pub struct RateTable {
+    currency: String,
+    rates: Vec<ExchangeRate>,
+}
+
+impl RateTable {
+    pub fn new(currency: String, rates: Vec<ExchangeRate>) -> Self {
+        RateTable { currency, rates }
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// Returns how many units of the group currency one unit of `from` was
+    /// worth on `date`, using the latest rate that took effect by then. A rate
+    /// only stored the other way round is inverted.
+    pub fn rate(&self, from: &str, date: NaiveDate) -> AppResult<Decimal> {
+        if from == self.currency {
+            return Ok(Decimal::ONE);
+        }
+
+        let latest = |base: &str, quote: &str| {
+            self.rates
+                .iter()
+                .filter(|r| {
+                    r.base_currency == base && r.quote_currency == quote && r.effective_date <= date
+                })
+                .max_by_key(|r| r.effective_date)
+        };
+
+        match (latest(from, &self.currency), latest(&self.currency, from)) {
+            (Some(direct), Some(inverse)) if inverse.effective_date > direct.effective_date => {
+                Ok(invert(inverse.rate))
+            }
+            (Some(direct), _) => Ok(direct.rate),
+            (None, Some(inverse)) => Ok(invert(inverse.rate)),
+            (None, None) => Err(ExpenseError::MissingExchangeRate(format!(
+                "{} to {} on {}",
+                from, self.currency, date
+            ))
+            .into()),
+        }
+    }
+}
+
+/// Inverts a stored rate, keeping the precision rates are stored with.
+fn invert(rate: Decimal) -> Decimal {
+    (Decimal::ONE / rate).round_dp(8)
+}
+
+/// Parses exchange rates from CSV text with the columns
+/// `base_currency,quote_currency,rate,effective_date`. A header row, blank
+/// lines and lines starting with `#` are skipped.
+pub fn parse_rates_csv(text: &str) -> AppResult<Vec<CreateExchangeRate>> {
+    let mut rates = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("base_currency") {
+            continue;
+        }
+
+        let invalid_line = || {
+            ValidationError::InvalidFormat(format!(
+                "base_currency,quote_currency,rate,effective_date on line {}",
+                i + 1
+            ))
+        };
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [base, quote, rate, date] = fields[..] else {
+            return Err(invalid_line().into());
+        };
+
+        let rate = CreateExchangeRate {
+            base_currency: base.to_string(),
+            quote_currency: quote.to_string(),
+            rate: Decimal::from_str(rate).map_err(|_| invalid_line())?,
+            effective_date: NaiveDate::from_str(date).map_err(|_| invalid_line())?,
+        };
+        rate.validate()?;
+        rates.push(rate);
+    }
+    Ok(rates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::AppError;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        value.parse().unwrap()
+    }
+
+    fn rate(base: &str, quote: &str, value: &str, effective: &str) -> ExchangeRate {
+        ExchangeRate {
+            base_currency: base.to_string(),
+            quote_currency: quote.to_string(),
+            rate: dec(value),
+            effective_date: date(effective),
+        }
+    }
+
+    fn table(rates: Vec<ExchangeRate>) -> RateTable {
+        RateTable::new("INR".to_string(), rates)
+    }
+
+    #[test]
+    fn same_currency_needs_no_rate() {
+        assert_eq!(
+            table(vec![]).rate("INR", date("2025-01-01")).unwrap(),
+            Decimal::ONE
+        );
+    }
+
+    #[test]
+    fn uses_latest_rate_on_or_before_the_date() {
+        let rates = table(vec![
+            rate("USD", "INR", "82.00", "2025-01-01"),
+            rate("USD", "INR", "83.50", "2025-02-01"),
+            rate("USD", "INR", "84.00", "2025-03-01"),
+        ]);
+
+        assert_eq!(rates.rate("USD", date("2025-01-15")).unwrap(), dec("82.00"));
+        assert_eq!(rates.rate("USD", date("2025-02-01")).unwrap(), dec("83.50"));
+        assert_eq!(rates.rate("USD", date("2025-12-31")).unwrap(), dec("84.00"));
+    }
+
+    #[test]
+    fn missing_rate_is_an_error() {
+        let rates = table(vec![rate("USD", "INR", "82.00", "2025-01-01")]);
+
+        for (from, on) in [("USD", "2024-12-31"), ("EUR", "2025-06-01")] {
+            assert!(matches!(
+                rates.rate(from, date(on)),
+                Err(AppError::Expense(ExpenseError::MissingExchangeRate(_)))
+            ));
+        }
+    }
+
+    #[test]
+    fn inverts_a_rate_stored_the_other_way_round() {
+        let rates = table(vec![rate("INR", "EUR", "0.0125", "2025-01-01")]);
+        assert_eq!(rates.rate("EUR", date("2025-01-01")).unwrap(), dec("80"));
+    }
+
+    #[test]
+    fn newer_inverse_rate_wins_over_older_direct_one() {
+        let rates = table(vec![
+            rate("USD", "INR", "82.00", "2025-01-01"),
+            rate("INR", "USD", "0.0125", "2025-02-01"),
+        ]);
+
+        assert_eq!(rates.rate("USD", date("2025-01-31")).unwrap(), dec("82.00"));
+        assert_eq!(rates.rate("USD", date("2025-02-01")).unwrap(), dec("80"));
+    }
+
+    #[test]
+    fn convert_rounds_halves_away_from_zero() {
+        assert_eq!(convert(dec("10.00"), dec("0.1235")), dec("1.24"));
+        assert_eq!(convert(dec("-10.00"), dec("0.1235")), dec("-1.24"));
+        assert_eq!(convert(dec("3.33"), dec("1")), dec("3.33"));
+    }
+
+    #[test]
+    fn parses_rates_skipping_header_comments_and_blank_lines() {
+        let text = "base_currency,quote_currency,rate,effective_date\n\
+                    # monthly rates\n\
+                    \n\
+                    USD, INR, 83.25, 2025-01-01\n\
+                    EUR,INR,90.1,2025-01-01\n";
+
+        let rates = parse_rates_csv(text).unwrap();
+
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rates[0].base_currency, "USD");
+        assert_eq!(rates[0].quote_currency, "INR");
+        assert_eq!(rates[0].rate, dec("83.25"));
+        assert_eq!(rates[0].effective_date, date("2025-01-01"));
+        assert_eq!(rates[1].base_currency, "EUR");
+    }
+
+    #[test]
+    fn rejects_bad_rows() {
+        for text in [
+            "USD,INR,83.25",
+            "USD,INR,83.25,2025-01-01,extra",
+            "USD,INR,lots,2025-01-01",
+            "USD,INR,83.25,01/01/2025",
+            "usd,INR,83.25,2025-01-01",
+            "USD,USD,1,2025-01-01",
+            "USD,INR,0,2025-01-01",
+            "USD,INR,-1,2025-01-01",
+        ] {
+            assert!(parse_rates_csv(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn bad_row_error_names_the_line() {
+        let err = parse_rates_csv("USD,INR,1,2025-01-01\nnot a rate\n").unwrap_err();
+        assert!(matches!(
+            err,
+            AppError::Validation(ValidationError::InvalidFormat(ref msg)) if msg.ends_with("line 2")
+        ));
+    }
+}
@@ -1,11 +1,41 @@
 use sqlx::{PgPool, postgres::PgPoolOptions};
+use std::time::Duration;
 
-pub async fn create_pool() -> Result<PgPool, sqlx::Error> {
-    let database_url = std::env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set");
-    
+use crate::config::DatabaseConfig;
+use crate::errors::{AppError, AppResult, DatabaseError};
+
+pub async fn create_pool(config: &DatabaseConfig) -> AppResult<PgPool> {
     PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+        .idle_timeout(config.idle_timeout_secs.map(Duration::from_secs))
+        .connect(&config.url)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to connect to database: {}", e)))
+}
+
+/// Applies the migrations in `migrations/` that haven't run yet. They are
+/// embedded at build time, so the binary doesn't need the directory.
+pub async fn run_migrations(pool: &PgPool) -> AppResult<()> {
+    // A schema created by running the SQL files by hand has no record of
+    // which migrations it has, and running them all again would fail halfway
+    let untracked = sqlx::query_scalar::<_, bool>(
+        "SELECT to_regclass('users') IS NOT NULL AND to_regclass('_sqlx_migrations') IS NULL",
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    if untracked {
+        return Err(AppError::Internal(
+            "The database has tables but no migration history. Record the migrations already \
+             applied with baseline-migrations.sh, or set DATABASE_RUN_MIGRATIONS=false"
+                .to_string(),
+        ));
+    }
+
+    sqlx::migrate!("./migrations")
+        .run(pool)
         .await
-}
\ No newline at end of file
+        .map_err(|e| AppError::Internal(format!("Failed to run database migrations: {}", e)))
+}
@@ -13,6 +13,16 @@ pub enum AuthError{
     TokenExpired,
     InvalidToken,
     InsufficientPermissions,
+    SessionRevoked,
+    TooManyAttempts(u64),
+    EmailNotVerified,
+    InvalidTwoFactorCode,
+    TwoFactorAlreadyEnabled,
+    TwoFactorNotEnabled,
+    AccessTokenNotFound,
+    OidcNotConfigured,
+    OidcAccountNotLinked,
+    OidcError(String),
 }
 
 impl fmt::Display for AuthError{
@@ -26,7 +36,17 @@ impl fmt::Display for AuthError{
             AuthError::MissingJwtSecret=>write!(f,"JWT secret not configured"),
             AuthError::TokenExpired=>write!(f,"Authentication Token expired"),
             AuthError::InvalidToken=>write!(f,"Invalid Authentication Token"),
-            AuthError::InsufficientPermissions=>write!(f,"Insufficient permissions")
+            AuthError::InsufficientPermissions=>write!(f,"Insufficient permissions"),
+            AuthError::SessionRevoked=>write!(f,"Session has been revoked"),
+            AuthError::TooManyAttempts(secs)=>write!(f,"Too many attempts, retry after {} seconds",secs),
+            AuthError::EmailNotVerified=>write!(f,"Email address not verified"),
+            AuthError::InvalidTwoFactorCode=>write!(f,"Invalid two-factor code"),
+            AuthError::TwoFactorAlreadyEnabled=>write!(f,"Two-factor authentication is already enabled"),
+            AuthError::TwoFactorNotEnabled=>write!(f,"Two-factor authentication is not enabled"),
+            AuthError::AccessTokenNotFound=>write!(f,"Access token not found"),
+            AuthError::OidcNotConfigured=>write!(f,"Single sign-on is not configured"),
+            AuthError::OidcAccountNotLinked=>write!(f,"No account is linked to this identity provider login"),
+            AuthError::OidcError(msg)=>write!(f,"Identity provider error: {}",msg)
         }
     }
 }
@@ -42,7 +62,17 @@ impl ResponseError for AuthError{
             AuthError::MissingJwtSecret=>StatusCode::INTERNAL_SERVER_ERROR,
             AuthError::TokenExpired=>StatusCode::UNAUTHORIZED,
             AuthError::InvalidToken=>StatusCode::UNAUTHORIZED,
-            AuthError::InsufficientPermissions=>StatusCode::FORBIDDEN
+            AuthError::InsufficientPermissions=>StatusCode::FORBIDDEN,
+            AuthError::SessionRevoked=>StatusCode::UNAUTHORIZED,
+            AuthError::TooManyAttempts(_)=>StatusCode::TOO_MANY_REQUESTS,
+            AuthError::EmailNotVerified=>StatusCode::FORBIDDEN,
+            AuthError::InvalidTwoFactorCode=>StatusCode::UNAUTHORIZED,
+            AuthError::TwoFactorAlreadyEnabled=>StatusCode::CONFLICT,
+            AuthError::TwoFactorNotEnabled=>StatusCode::BAD_REQUEST,
+            AuthError::AccessTokenNotFound=>StatusCode::NOT_FOUND,
+            AuthError::OidcNotConfigured=>StatusCode::NOT_FOUND,
+            AuthError::OidcAccountNotLinked=>StatusCode::FORBIDDEN,
+            AuthError::OidcError(_)=>StatusCode::BAD_GATEWAY
         }
     }
     fn error_response(&self)->HttpResponse{
@@ -88,6 +118,88 @@ impl ResponseError for AuthError{
                     "status": status.as_u16()
                 })
             }
+            AuthError::SessionRevoked => {
+                serde_json::json!({
+                    "error": "Session revoked",
+                    "message": "This session has been logged out or revoked",
+                    "code": "AUTH_SESSION_REVOKED",
+                    "status": status.as_u16()
+                })
+            }
+            AuthError::TooManyAttempts(secs) => {
+                serde_json::json!({
+                    "error": "Too many attempts",
+                    "message": format!("Too many attempts, try again in {} seconds", secs),
+                    "code": "AUTH_TOO_MANY_ATTEMPTS",
+                    "retry_after": secs,
+                    "status": status.as_u16()
+                })
+            }
+            AuthError::EmailNotVerified => {
+                serde_json::json!({
+                    "error": "Email not verified",
+                    "message": "Verify your email address before logging in",
+                    "code": "AUTH_EMAIL_NOT_VERIFIED",
+                    "status": status.as_u16()
+                })
+            }
+            AuthError::InvalidTwoFactorCode => {
+                serde_json::json!({
+                    "error": "Invalid two-factor code",
+                    "message": "The authentication or recovery code is incorrect",
+                    "code": "AUTH_INVALID_2FA_CODE",
+                    "status": status.as_u16()
+                })
+            }
+            AuthError::TwoFactorAlreadyEnabled => {
+                serde_json::json!({
+                    "error": "Two-factor already enabled",
+                    "message": "Two-factor authentication is already enabled for this account",
+                    "code": "AUTH_2FA_ALREADY_ENABLED",
+                    "status": status.as_u16()
+                })
+            }
+            AuthError::TwoFactorNotEnabled => {
+                serde_json::json!({
+                    "error": "Two-factor not enabled",
+                    "message": "Two-factor authentication is not set up for this account",
+                    "code": "AUTH_2FA_NOT_ENABLED",
+                    "status": status.as_u16()
+                })
+            }
+            AuthError::AccessTokenNotFound => {
+                serde_json::json!({
+                    "error": "Access token not found",
+                    "message": "No access token found with the provided ID",
+                    "code": "AUTH_ACCESS_TOKEN_NOT_FOUND",
+                    "status": status.as_u16()
+                })
+            }
+            AuthError::OidcNotConfigured => {
+                serde_json::json!({
+                    "error": "Single sign-on not configured",
+                    "message": "Single sign-on is not available on this server",
+                    "code": "AUTH_OIDC_NOT_CONFIGURED",
+                    "status": status.as_u16()
+                })
+            }
+            AuthError::OidcAccountNotLinked => {
+                serde_json::json!({
+                    "error": "Account not linked",
+                    "message": "No account is linked to this login; ask an administrator for access",
+                    "code": "AUTH_OIDC_ACCOUNT_NOT_LINKED",
+                    "status": status.as_u16()
+                })
+            }
+            AuthError::OidcError(msg) => {
+                log::error!("OIDC error: {}", msg);
+                serde_json::json!({
+                    "error": "Identity provider error",
+                    "message": "Could not complete sign-in with the identity provider",
+                    "code": "AUTH_OIDC_ERROR",
+                    "status": status.as_u16()
+                })
+            }
             AuthError::DatabaseError(msg)=>{
                 log::error!("Datbase error: {}",msg);
                 serde_json::json!({
@@ -125,6 +237,10 @@ impl ResponseError for AuthError{
                 })
             }
         };
-        HttpResponse::build(status).json(error_response)
+        let mut response = HttpResponse::build(status);
+        if let AuthError::TooManyAttempts(secs) = self {
+            response.insert_header(("Retry-After", secs.to_string()));
+        }
+        response.json(error_response)
     }
 }
\ No newline at end of file
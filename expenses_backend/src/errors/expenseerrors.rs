@@ -9,6 +9,7 @@ pub enum ExpenseError {
     CalculationError(String),
     InsufficientFunds,
     DuplicateExpense,
+    MissingExchangeRate(String),
 }
 
 impl fmt::Display for ExpenseError {
@@ -19,6 +20,9 @@ impl fmt::Display for ExpenseError {
             ExpenseError::CalculationError(msg) => write!(f, "Calculation error: {}", msg),
             ExpenseError::InsufficientFunds => write!(f, "Insufficient funds for this operation"),
             ExpenseError::DuplicateExpense => write!(f, "Duplicate expense detected"),
+            ExpenseError::MissingExchangeRate(msg) => {
+                write!(f, "No exchange rate available: {}", msg)
+            }
         }
     }
 }
@@ -27,9 +31,9 @@ impl ResponseError for ExpenseError {
     fn status_code(&self) -> StatusCode {
         match self {
             ExpenseError::NotFound => StatusCode::NOT_FOUND,
-            ExpenseError::InvalidAmount(_) | ExpenseError::DuplicateExpense => {
-                StatusCode::BAD_REQUEST
-            }
+            ExpenseError::InvalidAmount(_)
+            | ExpenseError::DuplicateExpense
+            | ExpenseError::MissingExchangeRate(_) => StatusCode::BAD_REQUEST,
             ExpenseError::InsufficientFunds => StatusCode::PAYMENT_REQUIRED,
             ExpenseError::CalculationError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -9,6 +9,14 @@ pub enum GroupError {
     AlreadyMember,
     InsufficientPermissions,
     GroupFull,
+    OutstandingBalance(String),
+    LastMember,
+    LastOwner,
+    InvalidInvitation,
+    InvitationNotFound,
+    InvitationExpired,
+    InvitationRevoked,
+    InvitationUsedUp,
 }
 
 impl fmt::Display for GroupError {
@@ -21,6 +29,18 @@ impl fmt::Display for GroupError {
                 write!(f, "Insufficient permissions for this group operation")
             }
             GroupError::GroupFull => write!(f, "Group has reached maximum capacity"),
+            GroupError::OutstandingBalance(balance) => {
+                write!(f, "Member still has an outstanding balance of {}", balance)
+            }
+            GroupError::LastMember => write!(f, "Cannot remove the last member of a group"),
+            GroupError::LastOwner => {
+                write!(f, "A group must keep at least one owner")
+            }
+            GroupError::InvalidInvitation => write!(f, "Invitation link is invalid"),
+            GroupError::InvitationNotFound => write!(f, "Invitation not found"),
+            GroupError::InvitationExpired => write!(f, "Invitation has expired"),
+            GroupError::InvitationRevoked => write!(f, "Invitation has been revoked"),
+            GroupError::InvitationUsedUp => write!(f, "Invitation has reached its maximum uses"),
         }
     }
 }
@@ -28,9 +48,16 @@ impl fmt::Display for GroupError {
 impl ResponseError for GroupError {
     fn status_code(&self) -> StatusCode {
         match self {
-            GroupError::NotFound => StatusCode::NOT_FOUND,
+            GroupError::NotFound | GroupError::InvitationNotFound => StatusCode::NOT_FOUND,
             GroupError::NotAMember | GroupError::InsufficientPermissions => StatusCode::FORBIDDEN,
             GroupError::AlreadyMember | GroupError::GroupFull => StatusCode::BAD_REQUEST,
+            GroupError::OutstandingBalance(_) | GroupError::LastMember | GroupError::LastOwner => {
+                StatusCode::CONFLICT
+            }
+            GroupError::InvalidInvitation => StatusCode::BAD_REQUEST,
+            GroupError::InvitationExpired
+            | GroupError::InvitationRevoked
+            | GroupError::InvitationUsedUp => StatusCode::GONE,
         }
     }
 
@@ -1,10 +1,13 @@
 use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
 use std::fmt;
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub enum UserError {
     NotFound,
+    /// Some of the users a request refers to don't exist.
+    UsersNotFound(Vec<Uuid>),
     EmailAlreadyExists,
     UsernameAlreadyExists,
     InvalidCredentials,
@@ -15,6 +18,10 @@ impl fmt::Display for UserError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             UserError::NotFound => write!(f, "User not found"),
+            UserError::UsersNotFound(ids) => {
+                let ids: Vec<String> = ids.iter().map(Uuid::to_string).collect();
+                write!(f, "Users not found: {}", ids.join(", "))
+            }
             UserError::EmailAlreadyExists => write!(f, "Email address already exists"),
             UserError::UsernameAlreadyExists => write!(f, "Username already exists"),
             UserError::InvalidCredentials => write!(f, "Invalid credentials"),
@@ -26,7 +33,7 @@ impl fmt::Display for UserError {
 impl ResponseError for UserError {
     fn status_code(&self) -> StatusCode {
         match self {
-            UserError::NotFound => StatusCode::NOT_FOUND,
+            UserError::NotFound | UserError::UsersNotFound(_) => StatusCode::NOT_FOUND,
             UserError::EmailAlreadyExists | UserError::UsernameAlreadyExists => {
                 StatusCode::CONFLICT
             }
@@ -35,9 +42,13 @@ impl ResponseError for UserError {
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(serde_json::json!({
+        let mut body = serde_json::json!({
             "error": "user_error",
             "message": self.to_string()
-        }))
+        });
+        if let UserError::UsersNotFound(ids) = self {
+            body["user_ids"] = serde_json::json!(ids);
+        }
+        HttpResponse::build(self.status_code()).json(body)
     }
 }
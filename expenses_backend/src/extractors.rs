@@ -0,0 +1,186 @@
+use actix_web::dev::Payload;
+use actix_web::http::Method;
+use actix_web::{FromRequest, HttpRequest, web};
+use sqlx::PgPool;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use uuid::Uuid;
+
+use crate::auth::{ACCESS_TOKEN_PREFIX, Claims, verify_access_token, verify_jwt};
+use crate::errors::{AppError, AppResult, AuthError, DatabaseError, GroupError, ValidationError};
+use crate::jwtkeys::JwtKeys;
+use crate::models::{GroupRole, PersonalAccessToken, TokenScope};
+
+/// The caller identified by the request's `Authorization: Bearer` token,
+/// which is either an access token from a login or a personal access token.
+#[derive(Debug)]
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    pub claims: Claims,
+    /// The personal access token the request was made with, if any.
+    pub access_token: Option<PersonalAccessToken>,
+}
+
+impl AuthenticatedUser {
+    /// Requires an admin who logged in themselves. Personal access tokens
+    /// are refused even for admins, since they can't be limited to admin
+    /// work.
+    pub fn require_admin(&self) -> AppResult<()> {
+        if !self.claims.is_admin || self.access_token.is_some() {
+            return Err(AuthError::InsufficientPermissions.into());
+        }
+        Ok(())
+    }
+
+    /// Refuses personal access tokens, for account changes that need the
+    /// user to have logged in themselves.
+    pub fn require_session(&self) -> AppResult<()> {
+        if self.access_token.is_some() {
+            return Err(AuthError::InsufficientPermissions.into());
+        }
+        Ok(())
+    }
+
+    /// The groups the caller is limited to, if the request came with a
+    /// personal access token restricted to some groups.
+    pub fn allowed_groups(&self) -> Option<&[Uuid]> {
+        self.access_token
+            .as_ref()
+            .and_then(|token| token.group_ids.as_deref())
+    }
+
+    pub fn require_group_access(&self, group_id: Uuid) -> AppResult<()> {
+        if self
+            .allowed_groups()
+            .is_some_and(|groups| !groups.contains(&group_id))
+        {
+            return Err(AuthError::InsufficientPermissions.into());
+        }
+        Ok(())
+    }
+
+    /// Refuses personal access tokens limited to some groups, for requests
+    /// that are not about any one group, such as creating a new one.
+    pub fn require_unrestricted(&self) -> AppResult<()> {
+        if self.allowed_groups().is_some() {
+            return Err(AuthError::InsufficientPermissions.into());
+        }
+        Ok(())
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move { authenticate(&req).await })
+    }
+}
+
+/// An authenticated caller who belongs to the group named by the route's
+/// `{group_id}` segment, along with their role in it.
+#[derive(Debug)]
+pub struct GroupMember {
+    pub user_id: Uuid,
+    pub group_id: Uuid,
+    pub role: GroupRole,
+}
+
+impl GroupMember {
+    pub fn require(&self, required: GroupRole) -> AppResult<()> {
+        self.role.require(required)
+    }
+}
+
+impl FromRequest for GroupMember {
+    type Error = AppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let user = authenticate(&req).await?;
+
+            let group_id = req
+                .match_info()
+                .get("group_id")
+                .and_then(|id| Uuid::from_str(id).ok())
+                .ok_or(ValidationError::InvalidFormat(
+                    "valid UUID for group_id".to_string(),
+                ))?;
+            user.require_group_access(group_id)?;
+
+            let role = sqlx::query_scalar::<_, GroupRole>(
+                "SELECT role FROM group_members WHERE group_id = $1 AND user_id = $2",
+            )
+            .bind(group_id)
+            .bind(user.user_id)
+            .fetch_optional(database_pool(&req)?)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+            .ok_or(GroupError::NotAMember)?;
+
+            Ok(GroupMember {
+                user_id: user.user_id,
+                group_id,
+                role,
+            })
+        })
+    }
+}
+
+fn database_pool(req: &HttpRequest) -> AppResult<&PgPool> {
+    req.app_data::<web::Data<PgPool>>()
+        .map(|pool| pool.get_ref())
+        .ok_or(AppError::Internal(
+            "database pool not configured".to_string(),
+        ))
+}
+
+async fn authenticate(req: &HttpRequest) -> AppResult<AuthenticatedUser> {
+    let keys = req
+        .app_data::<web::Data<JwtKeys>>()
+        .ok_or(AuthError::MissingJwtSecret)?;
+
+    let auth_header = req
+        .headers()
+        .get("Authorization")
+        .ok_or(AuthError::InvalidCredentials)?;
+
+    let auth_str = auth_header
+        .to_str()
+        .map_err(|e| AuthError::JwtError(format!("Invalid auth header format: {}", e)))?;
+
+    let token = auth_str
+        .strip_prefix("Bearer ")
+        .ok_or(AuthError::InvalidCredentials)?;
+
+    if token.starts_with(ACCESS_TOKEN_PREFIX) {
+        let (claims, access_token) = verify_access_token(token, database_pool(req)?).await?;
+        // Read-only tokens can look but not touch
+        if access_token.scope == TokenScope::Read
+            && !matches!(*req.method(), Method::GET | Method::HEAD)
+        {
+            return Err(AuthError::InsufficientPermissions.into());
+        }
+
+        return Ok(AuthenticatedUser {
+            user_id: access_token.user_id,
+            claims,
+            access_token: Some(access_token),
+        });
+    }
+
+    let claims = verify_jwt(token, keys, database_pool(req)?).await?;
+    let user_id = Uuid::from_str(&claims.user_id)
+        .map_err(|_| ValidationError::InvalidFormat("valid UUID".to_string()))?;
+
+    Ok(AuthenticatedUser {
+        user_id,
+        claims,
+        access_token: None,
+    })
+}
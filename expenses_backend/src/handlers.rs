@@ -1,86 +1,465 @@
+use actix_web::cookie::time::Duration as CookieDuration;
+use actix_web::cookie::{Cookie, SameSite};
 use actix_web::{HttpRequest, HttpResponse, web};
+use chrono::Utc;
 use rust_decimal::Decimal;
-use sqlx::PgPool;
+use sqlx::{Acquire, PgConnection, PgPool, Postgres, QueryBuilder};
+use std::collections::HashMap;
 use std::str::FromStr;
 use uuid::Uuid;
 
 use crate::auth::*;
-use crate::authservice::AuthService;
+use crate::authservice::{
+    AccountSettings, AuthService, LoginOutcome, OIDC_LOGIN_TTL_MINUTES, map_user_write_error,
+};
+use crate::config::AppConfig;
+use crate::currency::{DEFAULT_CURRENCY, RateTable, convert, parse_rates_csv};
 use crate::errors::{
     AppError, AppResult, AuthError, DatabaseError, ExpenseError, GroupError, UserError,
     ValidationError,
 };
+use crate::extractors::{AuthenticatedUser, GroupMember};
+use crate::jwtkeys::JwtKeys;
+use crate::mailer::{Email, Mailer};
 use crate::models::*;
-
+use crate::oidc::OidcClient;
+use crate::ratelimit::LoginThrottle;
+use crate::settlements::{net_pairwise_debts, simplify_debts};
+use crate::splits::{ComputedSplit, allocate, compute_splits};
+
+/// Lists a group's expenses a page at a time, most recently incurred first
+/// unless `sort` and `order` say otherwise. Pages are keyed on the last expense shown rather
+/// than an offset, so adding expenses meanwhile doesn't shift or repeat rows.
 pub async fn get_group_expenses(
     pool: web::Data<PgPool>,
-    path: web::Path<Uuid>,
-    req: HttpRequest,
+    member: GroupMember,
+    query: web::Query<ExpenseListQuery>,
 ) -> AppResult<HttpResponse> {
-    let claims = get_user_from_request(&req)?;
-    let user_id = Uuid::from_str(&claims.user_id)
-        .map_err(|_| ValidationError::InvalidFormat("valid UUID".to_string()))?;
-    let group_id = path.into_inner();
+    let group_id = member.group_id;
 
-    let is_member = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM group_members WHERE group_id=$1 AND user_id=$2)",
-    )
-    .bind(group_id)
-    .bind(user_id)
-    .fetch_one(pool.get_ref())
-    .await
-    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    query.validate()?;
+    let cursor = query.decode_cursor()?;
+    let limit = query.page_size();
 
-    if !is_member {
-        return Err(GroupError::NotAMember.into());
+    let mut count = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM expenses e");
+    push_expense_filters(&mut count, group_id, &query);
+    let total = count
+        .build_query_scalar::<i64>()
+        .fetch_one(pool.get_ref())
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    let mut select = QueryBuilder::<Postgres>::new(
+        "SELECT e.*, u.username FROM expenses e JOIN users u ON e.paid_by = u.id",
+    );
+    push_expense_filters(&mut select, group_id, &query);
+
+    // Expenses incurred on the same day list in the order they were entered
+    let keys: &[&str] = match query.sort {
+        ExpenseSortField::IncurredOn => &["e.incurred_on", "e.created_at", "e.id"],
+        ExpenseSortField::CreatedAt => &["e.created_at", "e.id"],
+        ExpenseSortField::Amount => &["e.amount", "e.id"],
+    };
+    let (direction, after) = match query.order {
+        SortOrder::Asc => ("ASC", ">"),
+        SortOrder::Desc => ("DESC", "<"),
+    };
+    if let Some(cursor) = &cursor {
+        select.push(format!(" AND ({}) {} (", keys.join(", "), after));
+        let mut values = select.separated(", ");
+        match query.sort {
+            ExpenseSortField::IncurredOn => {
+                values.push_bind(cursor.incurred_on);
+                values.push_bind(cursor.created_at);
+            }
+            ExpenseSortField::CreatedAt => {
+                values.push_bind(cursor.created_at);
+            }
+            ExpenseSortField::Amount => {
+                values.push_bind(cursor.amount);
+            }
+        }
+        values.push_bind(cursor.id);
+        select.push(")");
     }
+    let ordering: Vec<String> = keys
+        .iter()
+        .map(|key| format!("{} {}", key, direction))
+        .collect();
+    select.push(format!(" ORDER BY {} LIMIT ", ordering.join(", ")));
+    // One extra row tells whether another page follows
+    select.push_bind(limit + 1);
 
-    let expenses = sqlx::query!(
-        r#"SELECT e.*, u.username FROM expenses e JOIN users u ON e.paid_by = u.id WHERE e.group_id = $1 ORDER BY e.created_at DESC"#,
-        group_id
-    )
-    .fetch_all(pool.get_ref())
-    .await
-    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    let mut rows = select
+        .build_query_as::<ExpenseRow>()
+        .fetch_all(pool.get_ref())
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
 
-    let response: Vec<ExpenseResponse> = expenses
-        .into_iter()
-        .map(|e| ExpenseResponse {
+    let has_more = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+    let next_cursor = rows
+        .last()
+        .filter(|_| has_more)
+        .map(|row| {
+            ExpenseCursor {
+                sort: query.sort,
+                order: query.order,
+                incurred_on: row.expense.incurred_on,
+                created_at: row.expense.created_at,
+                amount: row.expense.amount,
+                id: row.expense.id,
+            }
+            .encode()
+        })
+        .transpose()?;
+
+    let rates = load_rate_table(pool.get_ref(), group_id).await?;
+
+    let mut expenses = Vec::with_capacity(rows.len());
+    for ExpenseRow {
+        expense: e,
+        username,
+    } in rows
+    {
+        let exchange_rate = rates.rate(&e.currency, e.incurred_on)?;
+        expenses.push(ExpenseResponse {
             id: e.id,
-            group_id: e.group_id.expect("group_id should not be null"),
-            paid_by: e.paid_by.expect("paid_by should not be null"),
+            group_id: e.group_id,
+            paid_by: e.paid_by,
             amount: e.amount,
+            converted_amount: convert(e.amount, exchange_rate),
+            exchange_rate,
+            currency: e.currency,
             description: e.description,
-            created_at: e.created_at.expect("created_at should not be null"),
-            username: e.username,
-        })
-        .collect();
+            category: e.category,
+            incurred_on: e.incurred_on,
+            incurred_at: e.incurred_at,
+            time_zone: e.time_zone,
+            created_at: e.created_at,
+            username,
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(ExpensePage {
+        expenses,
+        pagination: PageInfo {
+            limit,
+            total,
+            has_more,
+            next_cursor,
+        },
+    }))
+}
+
+#[derive(sqlx::FromRow)]
+struct ExpenseRow {
+    #[sqlx(flatten)]
+    expense: Expense,
+    username: String,
+}
+
+/// Restricts a query over `expenses e` to the group's live expenses that
+/// match the list filters.
+fn push_expense_filters(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    group_id: Uuid,
+    query: &ExpenseListQuery,
+) {
+    builder
+        .push(" WHERE e.group_id = ")
+        .push_bind(group_id)
+        .push(" AND e.deleted_at IS NULL");
+    if let Some(paid_by) = query.paid_by {
+        builder.push(" AND e.paid_by = ").push_bind(paid_by);
+    }
+    if let Some(from) = query.from {
+        builder.push(" AND e.incurred_on >= ").push_bind(from);
+    }
+    if let Some(to) = query.to {
+        builder.push(" AND e.incurred_on <= ").push_bind(to);
+    }
+    if let Some(min_amount) = query.min_amount {
+        builder.push(" AND e.amount >= ").push_bind(min_amount);
+    }
+    if let Some(max_amount) = query.max_amount {
+        builder.push(" AND e.amount <= ").push_bind(max_amount);
+    }
+    if let Some(q) = query.q.as_deref().filter(|q| !q.is_empty()) {
+        builder
+            .push(" AND e.description ILIKE ")
+            .push_bind(format!("%{}%", escape_like(q)));
+    }
+    if let Some(category) = &query.category {
+        builder
+            .push(" AND LOWER(e.category) = LOWER(")
+            .push_bind(category.clone())
+            .push(")");
+    }
+}
 
-    Ok(HttpResponse::Ok().json(response))
+/// Escapes the `LIKE` wildcards in user input so it matches literally.
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Publishes the public keys tokens are signed with, for services that need
+/// to verify them.
+pub async fn jwks(keys: web::Data<JwtKeys>) -> AppResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(keys.jwks()))
 }
 
 pub async fn login(
     pool: web::Data<PgPool>,
+    keys: web::Data<JwtKeys>,
+    throttle: web::Data<LoginThrottle>,
+    config: web::Data<AppConfig>,
     form: web::Json<LoginRequest>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    throttle.check_ip(&client_ip(&req))?;
+    form.validate()?;
+    throttle.check_account(&form.email)?;
+
+    let login = AuthService::login(
+        pool.get_ref(),
+        &form,
+        &keys,
+        &config.tokens,
+        config.accounts.require_verified_email,
+    )
+    .await;
+    let (tokens, user_response) = match login {
+        Ok(LoginOutcome::Authenticated(tokens, user_response)) => (tokens, user_response),
+        // The failure count is only cleared once the second factor is in too,
+        // so a known password doesn't buy unlimited guesses at the code
+        Ok(LoginOutcome::TwoFactorRequired(challenge_token)) => {
+            return Ok(HttpResponse::Ok().json(serde_json::json!({
+                "two_factor_required": true,
+                "challenge_token": challenge_token,
+                "message": "Two-factor authentication required"
+            })));
+        }
+        Err(AppError::Auth(AuthError::InvalidCredentials)) => {
+            throttle.record_failure(&form.email);
+            return Err(AuthError::InvalidCredentials.into());
+        }
+        Err(e) => return Err(e),
+    };
+    throttle.record_success(&form.email);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "token": tokens.token,
+        "refresh_token": tokens.refresh_token,
+        "expires_in": tokens.expires_in,
+        "user": user_response,
+        "message": "Login Successful"
+    })))
+}
+
+/// Second step of a login for accounts with two-factor authentication.
+pub async fn complete_two_factor_login(
+    pool: web::Data<PgPool>,
+    keys: web::Data<JwtKeys>,
+    config: web::Data<AppConfig>,
+    throttle: web::Data<LoginThrottle>,
+    form: web::Json<TwoFactorLogin>,
+    req: HttpRequest,
 ) -> AppResult<HttpResponse> {
+    throttle.check_ip(&client_ip(&req))?;
     form.validate()?;
 
-    let (token, user_response) = AuthService::login(pool.get_ref(), &form).await?;
+    let challenge = verify_two_factor_token(&form.challenge_token, &keys)?;
+    throttle.check_account(&challenge.email)?;
+
+    let login = AuthService::complete_two_factor_login(
+        pool.get_ref(),
+        &challenge,
+        &form.code,
+        &keys,
+        &config.tokens,
+    )
+    .await;
+    let (tokens, user_response) = match login {
+        Ok(result) => result,
+        Err(AppError::Auth(AuthError::InvalidTwoFactorCode)) => {
+            throttle.record_failure(&challenge.email);
+            return Err(AuthError::InvalidTwoFactorCode.into());
+        }
+        Err(e) => return Err(e),
+    };
+    throttle.record_success(&challenge.email);
     Ok(HttpResponse::Ok().json(serde_json::json!({
-        "token": token,
+        "token": tokens.token,
+        "refresh_token": tokens.refresh_token,
+        "expires_in": tokens.expires_in,
         "user": user_response,
         "message": "Login Successful"
     })))
 }
 
+/// Cookie holding the hash of a pending single sign-on login's state.
+const OIDC_STATE_COOKIE: &str = "oidc_state";
+
+/// Ties a single sign-on login to the browser that started it. The callback
+/// is refused unless the same browser presents the hash of its state, so a
+/// victim can't be signed in to someone else's account through a link to a
+/// login that person started.
+fn oidc_state_cookie(state_hash: String, accounts: &AccountSettings) -> Cookie<'static> {
+    let secure = accounts.app_url.starts_with("https://");
+    Cookie::build(OIDC_STATE_COOKIE, state_hash)
+        .path("/api/auth/oidc")
+        .http_only(true)
+        .secure(secure)
+        // The app and the API may be served from different sites, which
+        // only a secure cookie may cross
+        .same_site(if secure {
+            SameSite::None
+        } else {
+            SameSite::Lax
+        })
+        .max_age(CookieDuration::minutes(OIDC_LOGIN_TTL_MINUTES))
+        .finish()
+}
+
+/// Starts a single sign-on login, returning the identity provider URL the
+/// web app should send the user to.
+pub async fn begin_oidc_login(
+    pool: web::Data<PgPool>,
+    config: web::Data<AppConfig>,
+    oidc: web::Data<Option<OidcClient>>,
+) -> AppResult<HttpResponse> {
+    let oidc = oidc.as_ref().as_ref().ok_or(AuthError::OidcNotConfigured)?;
+
+    let (authorization_url, state) = AuthService::begin_oidc_login(pool.get_ref(), oidc).await?;
+    Ok(HttpResponse::Ok()
+        .cookie(oidc_state_cookie(hash_token(&state), &config.accounts))
+        .json(serde_json::json!({
+            "authorization_url": authorization_url
+        })))
+}
+
+pub async fn complete_oidc_login(
+    pool: web::Data<PgPool>,
+    keys: web::Data<JwtKeys>,
+    config: web::Data<AppConfig>,
+    oidc: web::Data<Option<OidcClient>>,
+    throttle: web::Data<LoginThrottle>,
+    form: web::Json<OidcCallback>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    let oidc = oidc.as_ref().as_ref().ok_or(AuthError::OidcNotConfigured)?;
+    throttle.check_ip(&client_ip(&req))?;
+    form.validate()?;
+
+    let started_here = req
+        .cookie(OIDC_STATE_COOKIE)
+        .is_some_and(|cookie| cookie.value() == hash_token(&form.state));
+    if !started_here {
+        return Err(AuthError::InvalidToken.into());
+    }
+    let mut used_state = oidc_state_cookie(String::new(), &config.accounts);
+    used_state.make_removal();
+
+    let outcome = AuthService::complete_oidc_login(
+        pool.get_ref(),
+        oidc,
+        &form,
+        &keys,
+        &config.tokens,
+        config.accounts.require_verified_email,
+    )
+    .await?;
+    let (tokens, user_response) = match outcome {
+        LoginOutcome::Authenticated(tokens, user_response) => (tokens, user_response),
+        LoginOutcome::TwoFactorRequired(challenge_token) => {
+            return Ok(HttpResponse::Ok()
+                .cookie(used_state)
+                .json(serde_json::json!({
+                    "two_factor_required": true,
+                    "challenge_token": challenge_token,
+                    "message": "Two-factor authentication required"
+                })));
+        }
+    };
+    Ok(HttpResponse::Ok()
+        .cookie(used_state)
+        .json(serde_json::json!({
+            "token": tokens.token,
+            "refresh_token": tokens.refresh_token,
+            "expires_in": tokens.expires_in,
+            "user": user_response,
+            "message": "Login Successful"
+        })))
+}
+
+/// The address of the connecting peer. Forwarding headers are ignored since
+/// any client can set them.
+fn client_ip(req: &HttpRequest) -> String {
+    req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+pub async fn refresh_token(
+    pool: web::Data<PgPool>,
+    keys: web::Data<JwtKeys>,
+    config: web::Data<AppConfig>,
+    form: web::Json<RefreshRequest>,
+) -> AppResult<HttpResponse> {
+    form.validate()?;
+
+    let tokens =
+        AuthService::refresh(pool.get_ref(), &form.refresh_token, &keys, &config.tokens).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+pub async fn logout(pool: web::Data<PgPool>, user: AuthenticatedUser) -> AppResult<HttpResponse> {
+    user.require_session()?;
+    let session_id = Uuid::from_str(&user.claims.jti)
+        .map_err(|_| ValidationError::InvalidFormat("valid UUID".to_string()))?;
+
+    AuthService::logout(pool.get_ref(), session_id).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Logged out"})))
+}
+
 pub async fn register(
     pool: web::Data<PgPool>,
+    throttle: web::Data<LoginThrottle>,
+    mailer: web::Data<dyn Mailer>,
+    config: web::Data<AppConfig>,
     form: web::Json<CreateUser>,
+    req: HttpRequest,
 ) -> AppResult<HttpResponse> {
+    throttle.check_ip(&client_ip(&req))?;
     form.validate()?;
+    throttle.check_account(&form.email)?;
 
+    // Repeated failures for one address, such as probing whether it is
+    // taken, count against it like failed logins do
     let user_response =
-        AuthService::register(pool.get_ref(), &form.email, &form.username, &form.password).await?;
+        match AuthService::register(pool.get_ref(), &form.email, &form.username, &form.password)
+            .await
+        {
+            Ok(user_response) => user_response,
+            Err(e) => {
+                throttle.record_failure(&form.email);
+                return Err(e);
+            }
+        };
+
+    // The account exists either way; a new link can be asked for through
+    // the resend endpoint, which works without logging in
+    if let Err(e) = send_verification_email(pool.get_ref(), &mailer, &config, &user_response).await
+    {
+        log::error!("Failed to send verification email: {}", e);
+    }
 
     Ok(HttpResponse::Created().json(serde_json::json!({
         "user": user_response,
@@ -88,253 +467,1268 @@ pub async fn register(
     })))
 }
 
-fn get_user_from_request(req: &HttpRequest) -> AppResult<Claims> {
-    let auth_header = req
-        .headers()
-        .get("Authorization")
-        .ok_or(AuthError::InvalidCredentials)?;
+/// Mails a password reset link. The response is the same, and comes back
+/// just as fast, whether or not the address belongs to an account, so it
+/// can't be used to probe for users.
+pub async fn request_password_reset(
+    pool: web::Data<PgPool>,
+    throttle: web::Data<LoginThrottle>,
+    mailer: web::Data<dyn Mailer>,
+    config: web::Data<AppConfig>,
+    form: web::Json<PasswordResetRequest>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    throttle.check_ip(&client_ip(&req))?;
+    form.validate()?;
+
+    let ttl = config.tokens.password_reset();
+    if let Some((user, token)) =
+        AuthService::request_password_reset(pool.get_ref(), &form.email, ttl).await?
+    {
+        let email = Email {
+            to: user.email,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Hi {},\n\nUse the link below to choose a new password. It expires in {} minutes.\n\n{}/reset-password?token={}\n\nIf you didn't ask for this, you can ignore this email.",
+                user.username,
+                ttl.num_minutes(),
+                config.accounts.app_url,
+                token
+            ),
+        };
+        spawn_email(&mailer, email, "password reset");
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "If an account exists for that email, a reset link has been sent"
+    })))
+}
+
+pub async fn confirm_password_reset(
+    pool: web::Data<PgPool>,
+    form: web::Json<PasswordResetConfirm>,
+) -> AppResult<HttpResponse> {
+    form.validate()?;
+
+    AuthService::reset_password(pool.get_ref(), &form.token, &form.new_password).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Password has been reset"})))
+}
+
+pub async fn request_email_verification(
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    config: web::Data<AppConfig>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    let user_response = AuthService::get_user_by_id(pool.get_ref(), user.user_id).await?;
+    if user_response.email_verified {
+        return Ok(
+            HttpResponse::Ok().json(serde_json::json!({"message": "Email already verified"}))
+        );
+    }
+
+    send_verification_email(pool.get_ref(), &mailer, &config, &user_response).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Verification email sent"})))
+}
+
+/// Mails a new verification link to an unverified account by address, for
+/// users who can't log in until they have verified. Like the password reset
+/// request, it answers the same way whether or not there is such an account.
+pub async fn resend_email_verification(
+    pool: web::Data<PgPool>,
+    throttle: web::Data<LoginThrottle>,
+    mailer: web::Data<dyn Mailer>,
+    config: web::Data<AppConfig>,
+    form: web::Json<ResendVerificationRequest>,
+    req: HttpRequest,
+) -> AppResult<HttpResponse> {
+    throttle.check_ip(&client_ip(&req))?;
+    form.validate()?;
+
+    let ttl = config.tokens.email_verification();
+    if let Some((user, token)) =
+        AuthService::resend_email_verification(pool.get_ref(), &form.email, ttl).await?
+    {
+        let email = verification_email(&config, user.email, &user.username, &token);
+        spawn_email(&mailer, email, "verification");
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "If an unverified account exists for that email, a verification link has been sent"
+    })))
+}
+
+pub async fn confirm_email_verification(
+    pool: web::Data<PgPool>,
+    form: web::Json<EmailVerificationConfirm>,
+) -> AppResult<HttpResponse> {
+    form.validate()?;
+
+    AuthService::verify_email(pool.get_ref(), &form.token).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Email verified"})))
+}
+
+async fn send_verification_email(
+    pool: &PgPool,
+    mailer: &web::Data<dyn Mailer>,
+    config: &AppConfig,
+    user: &UserResponse,
+) -> AppResult<()> {
+    let ttl = config.tokens.email_verification();
+    let token = AuthService::request_email_verification(pool, user.id, ttl).await?;
+    send_email(
+        mailer,
+        verification_email(config, user.email.clone(), &user.username, &token),
+    )
+    .await
+}
+
+fn verification_email(config: &AppConfig, to: String, username: &str, token: &str) -> Email {
+    Email {
+        to,
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Hi {},\n\nConfirm your email address by opening the link below. It expires in {} hours.\n\n{}/verify-email?token={}",
+            username,
+            config.tokens.email_verification().num_hours(),
+            config.accounts.app_url,
+            token
+        ),
+    }
+}
+
+/// Sends mail on the blocking thread pool, since mailers do blocking I/O.
+async fn send_email(mailer: &web::Data<dyn Mailer>, email: Email) -> AppResult<()> {
+    let mailer = mailer.clone().into_inner();
+    web::block(move || mailer.send(&email))
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+}
+
+/// Sends an email without holding up the response. Endpoints that only mail
+/// existing accounts use it so their response time doesn't give away
+/// whether the account exists.
+fn spawn_email(mailer: &web::Data<dyn Mailer>, email: Email, kind: &'static str) {
+    let mailer = mailer.clone();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = send_email(&mailer, email).await {
+            log::error!("Failed to send {} email: {}", kind, e);
+        }
+    });
+}
+
+async fn is_member(pool: &PgPool, group_id: Uuid, user_id: Uuid) -> AppResult<bool> {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM group_members WHERE group_id = $1 AND user_id = $2)",
+    )
+    .bind(group_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()).into())
+}
+
+pub async fn create_user(
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    config: web::Data<AppConfig>,
+    form: web::Json<CreateUser>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+
+    form.validate()?;
+
+    let password_hash = hash_password_async(&form.password).await?;
+
+    let user = sqlx::query_as::<_, User>(
+        "INSERT INTO users (email,username,password_hash) VALUES ($1,$2,$3) RETURNING *",
+    )
+    .bind(&form.email)
+    .bind(&form.username)
+    .bind(&password_hash)
+    .fetch_one(pool.get_ref())
+    .await
+    .map_err(map_user_write_error)?;
+
+    let user_response = UserResponse {
+        id: user.id,
+        email: user.email,
+        username: user.username,
+        is_admin: user.is_admin,
+        email_verified: user.email_verified_at.is_some(),
+    };
+
+    // As with registration, the new user can ask for another link if this
+    // one doesn't arrive
+    if let Err(e) = send_verification_email(pool.get_ref(), &mailer, &config, &user_response).await
+    {
+        log::error!("Failed to send verification email: {}", e);
+    }
+
+    Ok(HttpResponse::Created().json(user_response))
+}
+
+pub async fn get_users(
+    pool: web::Data<PgPool>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+
+    let users = sqlx::query_as::<_, User>("SELECT * FROM users ORDER BY created_at")
+        .fetch_all(pool.get_ref())
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    let user_responses: Vec<UserResponse> = users
+        .into_iter()
+        .map(|u| UserResponse {
+            id: u.id,
+            email: u.email,
+            username: u.username,
+            is_admin: u.is_admin,
+            email_verified: u.email_verified_at.is_some(),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(user_responses))
+}
+
+pub async fn get_current_user(
+    pool: web::Data<PgPool>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    let user_response = AuthService::get_user_by_id(pool.get_ref(), user.user_id).await?;
+    Ok(HttpResponse::Ok().json(user_response))
+}
+
+pub async fn update_current_user(
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    config: web::Data<AppConfig>,
+    form: web::Json<UpdateProfile>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_session()?;
+    form.validate()?;
+
+    let (user_response, email_changed) =
+        AuthService::update_profile(pool.get_ref(), user.user_id, &form).await?;
+
+    if email_changed {
+        if let Err(e) =
+            send_verification_email(pool.get_ref(), &mailer, &config, &user_response).await
+        {
+            log::error!("Failed to send verification email: {}", e);
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(user_response))
+}
+
+pub async fn change_password(
+    pool: web::Data<PgPool>,
+    keys: web::Data<JwtKeys>,
+    config: web::Data<AppConfig>,
+    form: web::Json<ChangePassword>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_session()?;
+    form.validate()?;
+
+    let tokens = AuthService::change_password(
+        pool.get_ref(),
+        user.user_id,
+        &form.current_password,
+        &form.new_password,
+        &keys,
+        &config.tokens,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "token": tokens.token,
+        "refresh_token": tokens.refresh_token,
+        "expires_in": tokens.expires_in,
+        "message": "Password changed"
+    })))
+}
+
+pub async fn begin_two_factor_enrollment(
+    pool: web::Data<PgPool>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_session()?;
+    let enrollment = AuthService::begin_two_factor_enrollment(pool.get_ref(), user.user_id).await?;
+    Ok(HttpResponse::Ok().json(enrollment))
+}
+
+pub async fn enable_two_factor(
+    pool: web::Data<PgPool>,
+    form: web::Json<TwoFactorCode>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_session()?;
+    form.validate()?;
+
+    let recovery_codes =
+        AuthService::enable_two_factor(pool.get_ref(), user.user_id, &form.code).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "recovery_codes": recovery_codes,
+        "message": "Two-factor authentication enabled"
+    })))
+}
+
+pub async fn disable_two_factor(
+    pool: web::Data<PgPool>,
+    throttle: web::Data<LoginThrottle>,
+    form: web::Json<TwoFactorCode>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_session()?;
+    form.validate()?;
+    throttle.check_account(&user.claims.email)?;
+
+    match AuthService::disable_two_factor(pool.get_ref(), user.user_id, &form.code).await {
+        Ok(()) => {}
+        Err(AppError::Auth(AuthError::InvalidTwoFactorCode)) => {
+            throttle.record_failure(&user.claims.email);
+            return Err(AuthError::InvalidTwoFactorCode.into());
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Two-factor authentication disabled"
+    })))
+}
+
+pub async fn create_access_token(
+    pool: web::Data<PgPool>,
+    form: web::Json<CreateAccessToken>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_session()?;
+    form.validate()?;
+
+    let access_token =
+        AuthService::create_access_token(pool.get_ref(), user.user_id, &form).await?;
+    Ok(HttpResponse::Created().json(access_token))
+}
+
+pub async fn get_access_tokens(
+    pool: web::Data<PgPool>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_session()?;
+
+    let access_tokens = AuthService::get_access_tokens(pool.get_ref(), user.user_id).await?;
+    Ok(HttpResponse::Ok().json(access_tokens))
+}
+
+pub async fn revoke_access_token(
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_session()?;
+
+    AuthService::revoke_access_token(pool.get_ref(), user.user_id, path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Access token revoked"})))
+}
+
+pub async fn create_group(
+    pool: web::Data<PgPool>,
+    form: web::Json<CreateGroup>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_unrestricted()?;
+    form.validate()?;
+
+    let creator_id = user.user_id;
+    // The creator always joins, as owner, whether or not they listed
+    // themselves
+    let mut member_ids: Vec<Uuid> = form
+        .user_ids
+        .iter()
+        .copied()
+        .filter(|id| *id != creator_id)
+        .collect();
+    member_ids.sort();
+    member_ids.dedup();
+
+    let known_ids = sqlx::query_scalar::<_, Uuid>("SELECT id FROM users WHERE id = ANY($1)")
+        .bind(&member_ids)
+        .fetch_all(pool.get_ref())
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    if known_ids.len() != member_ids.len() {
+        let unknown_ids = member_ids
+            .into_iter()
+            .filter(|id| !known_ids.contains(id))
+            .collect();
+        return Err(UserError::UsersNotFound(unknown_ids).into());
+    }
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    let group = sqlx::query_as::<_, Group>(
+        "INSERT INTO groups (name,created_by,currency) VALUES ($1,$2,$3) RETURNING *",
+    )
+    .bind(&form.name)
+    .bind(creator_id)
+    .bind(form.currency.as_deref().unwrap_or(DEFAULT_CURRENCY))
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    sqlx::query("INSERT INTO group_members (group_id,user_id,role) VALUES ($1,$2,$3)")
+        .bind(group.id)
+        .bind(creator_id)
+        .bind(GroupRole::Owner)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    sqlx::query("INSERT INTO group_members (group_id,user_id) SELECT $1, UNNEST($2::UUID[])")
+        .bind(group.id)
+        .bind(&member_ids)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    tx.commit()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+    Ok(HttpResponse::Created().json(group))
+}
+
+pub async fn get_user_groups(
+    pool: web::Data<PgPool>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    let groups = sqlx::query_as::<_, Group>(
+        "SELECT g.* FROM groups g JOIN group_members gm ON g.id=gm.group_id 
+         WHERE gm.user_id=$1 AND ($2::UUID[] IS NULL OR g.id = ANY($2))",
+    )
+    .bind(user.user_id)
+    .bind(user.allowed_groups())
+    .fetch_all(pool.get_ref())
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(groups))
+}
+
+pub async fn add_group_member(
+    pool: web::Data<PgPool>,
+    member: GroupMember,
+    form: web::Json<AddMember>,
+) -> AppResult<HttpResponse> {
+    let group_id = member.group_id;
+
+    member.require(GroupRole::Manager)?;
+    member.require(form.role)?;
+
+    let user_exists =
+        sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1)")
+            .bind(form.user_id)
+            .fetch_one(pool.get_ref())
+            .await
+            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    if !user_exists {
+        return Err(UserError::NotFound.into());
+    }
+
+    if is_member(pool.get_ref(), group_id, form.user_id).await? {
+        return Err(GroupError::AlreadyMember.into());
+    }
+
+    sqlx::query("INSERT INTO group_members (group_id, user_id, role) VALUES ($1, $2, $3)")
+        .bind(group_id)
+        .bind(form.user_id)
+        .bind(form.role)
+        .execute(pool.get_ref())
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({"message": "Member added"})))
+}
+
+pub async fn remove_group_member(
+    pool: web::Data<PgPool>,
+    path: web::Path<(Uuid, Uuid)>,
+    query: web::Query<RemoveMemberQuery>,
+    member: GroupMember,
+) -> AppResult<HttpResponse> {
+    let (group_id, member_id) = path.into_inner();
+
+    member.require(GroupRole::Manager)?;
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    let members = lock_members(&mut tx, group_id).await?;
+    member.require(role_of(&members, member_id)?)?;
+
+    remove_member(&mut tx, &members, group_id, member_id, query.force).await?;
+
+    tx.commit()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Member removed"})))
+}
+
+pub async fn update_member_role(
+    pool: web::Data<PgPool>,
+    path: web::Path<(Uuid, Uuid)>,
+    form: web::Json<UpdateMemberRole>,
+    member: GroupMember,
+) -> AppResult<HttpResponse> {
+    let (group_id, member_id) = path.into_inner();
+
+    member.require(GroupRole::Manager)?;
+    member.require(form.role)?;
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    let members = lock_members(&mut tx, group_id).await?;
+    let current = role_of(&members, member_id)?;
+    member.require(current)?;
+
+    if current == GroupRole::Owner && form.role != GroupRole::Owner {
+        ensure_other_owner(&members)?;
+    }
+
+    sqlx::query("UPDATE group_members SET role = $1 WHERE group_id = $2 AND user_id = $3")
+        .bind(form.role)
+        .bind(group_id)
+        .bind(member_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    tx.commit()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Member role updated"})))
+}
+
+pub async fn leave_group(
+    pool: web::Data<PgPool>,
+    query: web::Query<RemoveMemberQuery>,
+    member: GroupMember,
+) -> AppResult<HttpResponse> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    let members = lock_members(&mut tx, member.group_id).await?;
+    remove_member(
+        &mut tx,
+        &members,
+        member.group_id,
+        member.user_id,
+        query.force,
+    )
+    .await?;
+
+    tx.commit()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Left group"})))
+}
+
+/// Locks a group's memberships for the rest of the transaction, so the member
+/// and owner counts checked against them cannot change before it commits.
+async fn lock_members(
+    conn: &mut PgConnection,
+    group_id: Uuid,
+) -> AppResult<Vec<(Uuid, GroupRole)>> {
+    sqlx::query_as::<_, (Uuid, GroupRole)>(
+        "SELECT user_id, role FROM group_members WHERE group_id = $1 FOR UPDATE",
+    )
+    .bind(group_id)
+    .fetch_all(conn)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()).into())
+}
+
+fn role_of(members: &[(Uuid, GroupRole)], user_id: Uuid) -> AppResult<GroupRole> {
+    members
+        .iter()
+        .find(|(id, _)| *id == user_id)
+        .map(|(_, role)| *role)
+        .ok_or(GroupError::NotAMember.into())
+}
+
+/// Takes a user out of a group whose memberships were locked with
+/// `lock_members`. A member who still owes or is owed money is only removed
+/// when `force` is set, and neither the last member nor the last owner can
+/// leave.
+async fn remove_member(
+    conn: &mut PgConnection,
+    members: &[(Uuid, GroupRole)],
+    group_id: Uuid,
+    user_id: Uuid,
+    force: bool,
+) -> AppResult<()> {
+    let role = role_of(members, user_id)?;
+
+    if members.len() <= 1 {
+        return Err(GroupError::LastMember.into());
+    }
+
+    if role == GroupRole::Owner {
+        ensure_other_owner(members)?;
+    }
+
+    if !force {
+        let balances = calculate_balances(&mut *conn, group_id).await?;
+        if let Some(balance) = balances
+            .iter()
+            .find(|b| b.user_id == user_id && !b.balance.is_zero())
+        {
+            return Err(GroupError::OutstandingBalance(balance.balance.to_string()).into());
+        }
+    }
+
+    sqlx::query("DELETE FROM group_members WHERE group_id = $1 AND user_id = $2")
+        .bind(group_id)
+        .bind(user_id)
+        .execute(conn)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    Ok(())
+}
+
+fn ensure_other_owner(members: &[(Uuid, GroupRole)]) -> AppResult<()> {
+    let owner_count = members
+        .iter()
+        .filter(|(_, role)| *role == GroupRole::Owner)
+        .count();
+
+    if owner_count <= 1 {
+        return Err(GroupError::LastOwner.into());
+    }
+    Ok(())
+}
+
+pub async fn create_invitation(
+    pool: web::Data<PgPool>,
+    keys: web::Data<JwtKeys>,
+    member: GroupMember,
+    form: web::Json<CreateInvitation>,
+) -> AppResult<HttpResponse> {
+    let group_id = member.group_id;
+
+    form.validate()?;
+
+    member.require(GroupRole::Owner)?;
+
+    let expires_at = Utc::now() + chrono::Duration::hours(form.expires_in_hours);
+
+    let invitation = sqlx::query_as::<_, GroupInvitation>(
+        "INSERT INTO group_invitations (group_id, created_by, max_uses, expires_at) VALUES ($1, $2, $3, $4) RETURNING *",
+    )
+    .bind(group_id)
+    .bind(member.user_id)
+    .bind(form.max_uses)
+    .bind(expires_at)
+    .fetch_one(pool.get_ref())
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    let token = create_invitation_token(invitation.id, group_id, invitation.expires_at, &keys)?;
+
+    Ok(HttpResponse::Created().json(InvitationResponse { invitation, token }))
+}
+
+pub async fn get_group_invitations(
+    pool: web::Data<PgPool>,
+    member: GroupMember,
+) -> AppResult<HttpResponse> {
+    member.require(GroupRole::Owner)?;
+
+    let invitations = sqlx::query_as::<_, GroupInvitation>(
+        "SELECT * FROM group_invitations WHERE group_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(member.group_id)
+    .fetch_all(pool.get_ref())
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(invitations))
+}
+
+pub async fn revoke_invitation(
+    pool: web::Data<PgPool>,
+    path: web::Path<(Uuid, Uuid)>,
+    member: GroupMember,
+) -> AppResult<HttpResponse> {
+    let (group_id, invitation_id) = path.into_inner();
+
+    member.require(GroupRole::Owner)?;
+
+    let result = sqlx::query(
+        "UPDATE group_invitations SET revoked_at = COALESCE(revoked_at, NOW()) WHERE id = $1 AND group_id = $2",
+    )
+    .bind(invitation_id)
+    .bind(group_id)
+    .execute(pool.get_ref())
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    if result.rows_affected() == 0 {
+        return Err(GroupError::InvitationNotFound.into());
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Invitation revoked"})))
+}
+
+/// Joins the caller to the group an invitation link points at. The row is
+/// locked while its use count is checked and bumped, so a link with
+/// `max_uses` can't be overused by concurrent accepts.
+pub async fn accept_invitation(
+    pool: web::Data<PgPool>,
+    keys: web::Data<JwtKeys>,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    let invitation_claims = verify_invitation_token(&path.into_inner(), &keys)?;
+    user.require_group_access(invitation_claims.group_id)?;
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    let invitation = sqlx::query_as::<_, GroupInvitation>(
+        "SELECT * FROM group_invitations WHERE id = $1 AND group_id = $2 FOR UPDATE",
+    )
+    .bind(invitation_claims.invitation_id)
+    .bind(invitation_claims.group_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+    .ok_or(GroupError::InvitationNotFound)?;
+
+    if invitation.revoked_at.is_some() {
+        return Err(GroupError::InvitationRevoked.into());
+    }
+    if invitation.expires_at <= Utc::now() {
+        return Err(GroupError::InvitationExpired.into());
+    }
+    if matches!(invitation.max_uses, Some(max) if invitation.use_count >= max) {
+        return Err(GroupError::InvitationUsedUp.into());
+    }
+
+    let already_member = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM group_members WHERE group_id = $1 AND user_id = $2)",
+    )
+    .bind(invitation.group_id)
+    .bind(user.user_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    if already_member {
+        return Err(GroupError::AlreadyMember.into());
+    }
+
+    sqlx::query("INSERT INTO group_members (group_id, user_id, role) VALUES ($1, $2, $3)")
+        .bind(invitation.group_id)
+        .bind(user.user_id)
+        .bind(GroupRole::Member)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
 
-    let auth_str = auth_header
-        .to_str()
-        .map_err(|e| AuthError::JwtError(format!("Invalid auth header format: {}", e)))?;
+    sqlx::query("UPDATE group_invitations SET use_count = use_count + 1 WHERE id = $1")
+        .bind(invitation.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
 
-    if !auth_str.starts_with("Bearer ") {
-        return Err(AuthError::InvalidCredentials.into());
-    }
+    let group = sqlx::query_as::<_, Group>("SELECT * FROM groups WHERE id = $1")
+        .bind(invitation.group_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
 
-    let token = &auth_str[7..];
-    let secret = std::env::var("JWT_SECRET").map_err(|_| AuthError::MissingJwtSecret)?;
+    tx.commit()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
 
-    verify_jwt(token, &secret)
+    Ok(HttpResponse::Ok().json(group))
 }
 
-pub async fn create_user(
+pub async fn add_expense(
     pool: web::Data<PgPool>,
-    form: web::Json<CreateUser>,
-    req: HttpRequest,
+    member: GroupMember,
+    form: web::Json<CreateExpense>,
 ) -> AppResult<HttpResponse> {
-    let claims = get_user_from_request(&req)?;
-
-    if !claims.is_admin {
-        return Err(AuthError::InsufficientPermissions.into());
-    }
+    let group_id = member.group_id;
 
     form.validate()?;
+    let date = form.expense_date(None)?;
 
-    let password_hash = hash_password(&form.password)?;
+    member.require(GroupRole::Member)?;
 
-    let user = sqlx::query_as::<_, User>(
-        "INSERT INTO users (email,username,password_hash) VALUES ($1,$2,$3) RETURNING *",
+    let participants = resolve_participants(pool.get_ref(), group_id, &form).await?;
+    let computed = compute_splits(form.amount, form.split_type, &participants, &form.splits)?;
+
+    let rates = load_rate_table(pool.get_ref(), group_id).await?;
+    let currency = form
+        .currency
+        .as_deref()
+        .unwrap_or(rates.currency())
+        .to_string();
+    rates.rate(&currency, date.incurred_on)?;
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    let expense = sqlx::query_as::<_, Expense>(
+        "INSERT INTO expenses (group_id, paid_by, amount, description, split_type, currency, category,
+         incurred_on, incurred_at, time_zone) 
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING *",
     )
-    .bind(&form.email)
-    .bind(&form.username)
-    .bind(&password_hash)
-    .fetch_one(pool.get_ref())
+    .bind(group_id)
+    .bind(member.user_id)
+    .bind(form.amount)
+    .bind(&form.description)
+    .bind(form.split_type)
+    .bind(&currency)
+    .bind(&form.category)
+    .bind(date.incurred_on)
+    .bind(date.incurred_at)
+    .bind(&date.time_zone)
+    .fetch_one(&mut *tx)
     .await
-    .map_err(|e| match e {
-        sqlx::Error::Database(db_err) if db_err.constraint().is_some() => {
-            if db_err.constraint().unwrap().contains("email") {
-                AppError::User(UserError::EmailAlreadyExists)
-            } else if db_err.constraint().unwrap().contains("username") {
-                AppError::User(UserError::UsernameAlreadyExists)
-            } else {
-                AppError::Database(DatabaseError::ConstraintViolation(db_err.to_string()))
-            }
-        }
-        _ => AppError::Database(DatabaseError::QueryFailed(e.to_string())),
-    })?;
-
-    Ok(HttpResponse::Created().json(UserResponse {
-        id: user.id,
-        email: user.email,
-        username: user.username,
-        is_admin: user.is_admin.unwrap_or(false),
-    }))
-}
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
 
-pub async fn get_users(pool: web::Data<PgPool>, req: HttpRequest) -> AppResult<HttpResponse> {
-    let claims = get_user_from_request(&req)?;
-    if !claims.is_admin {
-        return Err(AuthError::InsufficientPermissions.into());
-    }
+    let splits = insert_splits(&mut tx, expense.id, computed).await?;
 
-    let users = sqlx::query_as::<_, User>("SELECT * FROM users ORDER BY created_at")
-        .fetch_all(pool.get_ref())
+    tx.commit()
         .await
-        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
 
-    let user_responses: Vec<UserResponse> = users
-        .into_iter()
-        .map(|u| UserResponse {
-            id: u.id,
-            email: u.email,
-            username: u.username,
-            is_admin: u.is_admin.unwrap_or(false),
-        })
-        .collect();
-    Ok(HttpResponse::Ok().json(user_responses))
+    Ok(HttpResponse::Created().json(ExpenseWithSplits { expense, splits }))
 }
 
-pub async fn create_group(
+pub async fn update_expense(
     pool: web::Data<PgPool>,
-    form: web::Json<CreateGroup>,
-    req: HttpRequest,
+    path: web::Path<(Uuid, Uuid)>,
+    form: web::Json<CreateExpense>,
+    member: GroupMember,
 ) -> AppResult<HttpResponse> {
-    let claims = get_user_from_request(&req)?;
-    if !claims.is_admin {
-        return Err(AuthError::InsufficientPermissions.into());
-    }
+    let (group_id, expense_id) = path.into_inner();
 
     form.validate()?;
 
-    let creator_id = Uuid::from_str(&claims.user_id)
-        .map_err(|_| ValidationError::InvalidFormat("valid UUID".to_string()))?;
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
 
-    for user_id in &form.user_ids {
-        let user_exists =
-            sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1)")
-                .bind(user_id)
-                .fetch_one(pool.get_ref())
-                .await
-                .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    let existing = find_modifiable_expense(&mut tx, &member, expense_id).await?;
+    let date = form.expense_date(Some(&existing))?;
 
-        if !user_exists {
-            return Err(UserError::NotFound.into());
-        }
-    }
+    let participants = resolve_participants(pool.get_ref(), group_id, &form).await?;
+    let computed = compute_splits(form.amount, form.split_type, &participants, &form.splits)?;
 
-    let group = sqlx::query_as::<_, Group>(
-        "INSERT INTO groups (name,created_by) VALUES ($1,$2) RETURNING *",
+    let rates = load_rate_table(pool.get_ref(), group_id).await?;
+    let currency = form
+        .currency
+        .as_deref()
+        .unwrap_or(rates.currency())
+        .to_string();
+    rates.rate(&currency, date.incurred_on)?;
+
+    record_expense_audit(&mut tx, &existing, "updated", member.user_id).await?;
+
+    let expense = sqlx::query_as::<_, Expense>(
+        "UPDATE expenses SET amount = $1, description = $2, split_type = $3, currency = $4, 
+         category = $5, incurred_on = $6, incurred_at = $7, time_zone = $8, updated_at = NOW()
+         WHERE id = $9 RETURNING *",
     )
-    .bind(&form.name)
-    .bind(creator_id)
-    .fetch_one(pool.get_ref())
+    .bind(form.amount)
+    .bind(&form.description)
+    .bind(form.split_type)
+    .bind(&currency)
+    .bind(&form.category)
+    .bind(date.incurred_on)
+    .bind(date.incurred_at)
+    .bind(&date.time_zone)
+    .bind(expense_id)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
 
-    for user_id in &form.user_ids {
-        sqlx::query("INSERT INTO group_members (group_id,user_id) VALUES ($1,$2)")
-            .bind(group.id)
-            .bind(user_id)
-            .execute(pool.get_ref())
-            .await
-            .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
-    }
-    Ok(HttpResponse::Created().json(group))
-}
+    sqlx::query("DELETE FROM expense_splits WHERE expense_id = $1")
+        .bind(expense_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
 
-pub async fn get_user_groups(pool: web::Data<PgPool>, req: HttpRequest) -> AppResult<HttpResponse> {
-    let claims = get_user_from_request(&req)?;
-    let user_id = Uuid::from_str(&claims.user_id)
-        .map_err(|_| ValidationError::InvalidFormat("valid UUID".to_string()))?;
+    let splits = insert_splits(&mut tx, expense.id, computed).await?;
 
-    let groups = sqlx::query_as::<_, Group>(
-        "SELECT g.* FROM groups g JOIN group_members gm ON g.id=gm.group_id WHERE gm.user_id=$1",
-    )
-    .bind(user_id)
-    .fetch_all(pool.get_ref())
-    .await
-    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    tx.commit()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
 
-    Ok(HttpResponse::Ok().json(groups))
+    Ok(HttpResponse::Ok().json(ExpenseWithSplits { expense, splits }))
 }
 
-pub async fn add_expense(
+pub async fn delete_expense(
     pool: web::Data<PgPool>,
-    path: web::Path<Uuid>,
-    form: web::Json<CreateExpense>,
-    req: HttpRequest,
+    path: web::Path<(Uuid, Uuid)>,
+    member: GroupMember,
 ) -> AppResult<HttpResponse> {
-    let claims = get_user_from_request(&req)?;
-    let user_id = Uuid::from_str(&claims.user_id)
-        .map_err(|_| ValidationError::InvalidFormat("valid UUID".to_string()))?;
-    let group_id = path.into_inner();
+    let (_, expense_id) = path.into_inner();
 
-    form.validate()?;
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
 
-    let is_member = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM group_members WHERE group_id = $1 AND user_id = $2)",
+    let existing = find_modifiable_expense(&mut tx, &member, expense_id).await?;
+    record_expense_audit(&mut tx, &existing, "deleted", member.user_id).await?;
+
+    sqlx::query("UPDATE expenses SET deleted_at = NOW(), deleted_by = $1 WHERE id = $2")
+        .bind(member.user_id)
+        .bind(expense_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    tx.commit()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Expense deleted"})))
+}
+
+/// Loads a live expense and checks that the caller may change it: the payer
+/// can edit or delete their own expenses, managers and owners any of them.
+/// The row stays locked until the transaction ends, so a concurrent edit or
+/// delete waits and then finds the expense as this one left it.
+async fn find_modifiable_expense(
+    conn: &mut PgConnection,
+    member: &GroupMember,
+    expense_id: Uuid,
+) -> AppResult<Expense> {
+    let expense = sqlx::query_as::<_, Expense>(
+        "SELECT * FROM expenses WHERE id = $1 AND group_id = $2 AND deleted_at IS NULL
+         FOR UPDATE",
     )
-    .bind(group_id)
-    .bind(user_id)
-    .fetch_one(pool.get_ref())
+    .bind(expense_id)
+    .bind(member.group_id)
+    .fetch_optional(conn)
     .await
-    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+    .ok_or(ExpenseError::NotFound)?;
 
-    if !is_member {
-        return Err(GroupError::NotAMember.into());
+    if expense.paid_by == member.user_id {
+        member.require(GroupRole::Member)?;
+    } else {
+        member.require(GroupRole::Manager)?;
     }
 
-    let amount = Decimal::from_f64_retain(form.amount).ok_or(ValidationError::InvalidFormat(
-        "valid decimal number".to_string(),
-    ))?;
+    Ok(expense)
+}
 
-    let expense = sqlx::query_as::<_, Expense>(
-        "INSERT INTO expenses (group_id, paid_by, amount, description) 
-         VALUES ($1, $2, $3, $4) RETURNING *",
+async fn record_expense_audit(
+    conn: &mut PgConnection,
+    expense: &Expense,
+    action: &str,
+    changed_by: Uuid,
+) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO expense_audit (expense_id, action, changed_by, amount, currency, description, split_type, category,
+         incurred_on, incurred_at, time_zone) 
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
     )
-    .bind(group_id)
-    .bind(user_id)
-    .bind(amount)
-    .bind(&form.description)
-    .fetch_one(pool.get_ref())
+    .bind(expense.id)
+    .bind(action)
+    .bind(changed_by)
+    .bind(expense.amount)
+    .bind(&expense.currency)
+    .bind(&expense.description)
+    .bind(expense.split_type)
+    .bind(&expense.category)
+    .bind(expense.incurred_on)
+    .bind(expense.incurred_at)
+    .bind(&expense.time_zone)
+    .execute(conn)
     .await
     .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    Ok(())
+}
+
+/// Works out who an expense is charged to: the listed participants, the users
+/// named in the splits, or every member of the group for a plain equal split.
+async fn resolve_participants(
+    pool: &PgPool,
+    group_id: Uuid,
+    form: &CreateExpense,
+) -> AppResult<Vec<Uuid>> {
+    if form.participants.is_none() && form.split_type == SplitType::Equal {
+        return sqlx::query_scalar::<_, Uuid>(
+            "SELECT user_id FROM group_members WHERE group_id = $1",
+        )
+        .bind(group_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()).into());
+    }
+
+    let participants = form
+        .participants
+        .clone()
+        .unwrap_or_else(|| form.splits.iter().map(|s| s.user_id).collect());
+    for participant in &participants {
+        if !is_member(pool, group_id, *participant).await? {
+            return Err(GroupError::NotAMember.into());
+        }
+    }
+    Ok(participants)
+}
 
-    Ok(HttpResponse::Created().json(expense))
+async fn insert_splits(
+    conn: &mut PgConnection,
+    expense_id: Uuid,
+    computed: Vec<ComputedSplit>,
+) -> AppResult<Vec<ExpenseSplit>> {
+    let mut splits = Vec::with_capacity(computed.len());
+    for split in computed {
+        let split = sqlx::query_as::<_, ExpenseSplit>(
+            "INSERT INTO expense_splits (expense_id, user_id, amount, share) 
+             VALUES ($1, $2, $3, $4) RETURNING *",
+        )
+        .bind(expense_id)
+        .bind(split.user_id)
+        .bind(split.amount)
+        .bind(split.share)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+        splits.push(split);
+    }
+    Ok(splits)
 }
 
 pub async fn get_group_balances(
     pool: web::Data<PgPool>,
-    path: web::Path<Uuid>,
-    req: HttpRequest,
+    member: GroupMember,
 ) -> AppResult<HttpResponse> {
-    let claims = get_user_from_request(&req)?;
-    let user_id = Uuid::from_str(&claims.user_id)
-        .map_err(|_| ValidationError::InvalidFormat("valid UUID".to_string()))?;
+    let balances = calculate_balances(pool.get_ref(), member.group_id).await?;
 
-    let group_id = path.into_inner();
+    Ok(HttpResponse::Ok().json(balances))
+}
 
-    let is_member = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM group_members WHERE group_id=$1 AND user_id=$2)",
-    )
-    .bind(group_id)
-    .bind(user_id)
-    .fetch_one(pool.get_ref())
-    .await
-    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+pub async fn get_group_settlements(
+    pool: web::Data<PgPool>,
+    query: web::Query<SettlementQuery>,
+    member: GroupMember,
+) -> AppResult<HttpResponse> {
+    let group_id = member.group_id;
 
-    if !is_member {
-        return Err(GroupError::NotAMember.into());
-    }
+    let settlements = if query.simplify {
+        let balances = calculate_balances(pool.get_ref(), group_id).await?;
+        simplify_debts(&balances)
+    } else {
+        calculate_pairwise_debts(&pool, group_id).await?
+    };
 
-    let balances = calculate_balances(&pool, group_id).await?;
+    Ok(HttpResponse::Ok().json(settlements))
+}
 
-    Ok(HttpResponse::Ok().json(balances))
+/// Every expense split and payment in a group, converted into the group
+/// currency at the rate in effect on the day the expense was incurred or the
+/// payment recorded.
+struct GroupLedger {
+    /// `(participant, payer, amount)` for each share of an expense.
+    charges: Vec<(Uuid, Uuid, Decimal)>,
+    /// `(from, to, amount)` for each payment.
+    payments: Vec<(Uuid, Uuid, Decimal)>,
 }
 
-async fn calculate_balances(pool: &PgPool, group_id: Uuid) -> AppResult<Vec<Balance>> {
+async fn load_ledger<'a, A>(db: A, group_id: Uuid) -> AppResult<GroupLedger>
+where
+    A: Acquire<'a, Database = Postgres>,
+{
+    let mut conn = db
+        .acquire()
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    let rates = load_rate_table(&mut *conn, group_id).await?;
+
     let expenses = sqlx::query!(
-        "SELECT e.paid_by, e.amount, u.username 
-         FROM expenses e 
-         JOIN users u ON e.paid_by = u.id 
-         WHERE e.group_id = $1",
+        "SELECT e.id, e.paid_by, e.amount, e.currency, e.incurred_on
+        FROM expenses e
+        WHERE e.group_id = $1 AND e.deleted_at IS NULL",
+        group_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    let splits = sqlx::query!(
+        "SELECT s.expense_id, s.user_id, s.amount
+        FROM expense_splits s
+        JOIN expenses e ON s.expense_id = e.id
+        WHERE e.group_id = $1 AND e.deleted_at IS NULL
+        ORDER BY s.expense_id, s.user_id",
         group_id
     )
-    .fetch_all(pool)
+    .fetch_all(&mut *conn)
     .await
     .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
 
     let payments = sqlx::query!(
-        "SELECT p.from_user_id, p.to_user_id,p.amount
+        "SELECT p.from_user_id, p.to_user_id, p.amount, p.currency, p.created_at
         FROM payments p
-        WHERE p.group_id=$1",
+        WHERE p.group_id = $1",
         group_id
     )
-    .fetch_all(pool)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    let mut charges = Vec::with_capacity(splits.len());
+    for expense in expenses {
+        let expense_splits: Vec<_> = splits
+            .iter()
+            .filter(|s| s.expense_id == expense.id)
+            .collect();
+
+        // Convert the total once and spread it over the splits, so the
+        // converted shares still add up to exactly what the payer is owed.
+        let rate = rates.rate(&expense.currency, expense.incurred_on)?;
+        let weights: Vec<Decimal> = expense_splits.iter().map(|s| s.amount).collect();
+        let amounts = if rate == Decimal::ONE {
+            weights
+        } else {
+            allocate(convert(expense.amount, rate), &weights)
+        };
+
+        for (split, amount) in expense_splits.into_iter().zip(amounts) {
+            charges.push((split.user_id, expense.paid_by, amount));
+        }
+    }
+
+    let mut converted_payments = Vec::with_capacity(payments.len());
+    for payment in payments {
+        let rate = rates.rate(&payment.currency, payment.created_at.date_naive())?;
+        converted_payments.push((
+            payment.from_user_id,
+            payment.to_user_id,
+            convert(payment.amount, rate),
+        ));
+    }
+
+    Ok(GroupLedger {
+        charges,
+        payments: converted_payments,
+    })
+}
+
+async fn load_rate_table<'a, A>(db: A, group_id: Uuid) -> AppResult<RateTable>
+where
+    A: Acquire<'a, Database = Postgres>,
+{
+    let mut conn = db
+        .acquire()
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    let currency = sqlx::query_scalar::<_, String>("SELECT currency FROM groups WHERE id = $1")
+        .bind(group_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?
+        .ok_or(GroupError::NotFound)?;
+
+    let rates = sqlx::query_as::<_, ExchangeRate>(
+        "SELECT * FROM exchange_rates WHERE base_currency = $1 OR quote_currency = $1",
+    )
+    .bind(&currency)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    Ok(RateTable::new(currency, rates))
+}
+
+async fn calculate_pairwise_debts(pool: &PgPool, group_id: Uuid) -> AppResult<Vec<Settlement>> {
+    let ledger = load_ledger(pool, group_id).await?;
+
+    // Each participant owes the payer their split; a payment reduces what the
+    // payer owes the recipient, so it counts as a debt the other way round.
+    let mut debts = ledger.charges;
+    debts.extend(
+        ledger
+            .payments
+            .into_iter()
+            .map(|(from, to, amount)| (to, from, amount)),
+    );
+
+    let mut user_ids: Vec<Uuid> = debts.iter().flat_map(|(a, b, _)| [*a, *b]).collect();
+    user_ids.sort();
+    user_ids.dedup();
+    let usernames = load_usernames(pool, &user_ids).await?;
+
+    Ok(net_pairwise_debts(&debts, &usernames))
+}
+
+async fn load_usernames<'a, A>(db: A, user_ids: &[Uuid]) -> AppResult<HashMap<Uuid, String>>
+where
+    A: Acquire<'a, Database = Postgres>,
+{
+    let mut conn = db
+        .acquire()
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    let users = sqlx::query!(
+        "SELECT id, username FROM users WHERE id = ANY($1)",
+        user_ids
+    )
+    .fetch_all(&mut *conn)
     .await
     .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
 
+    Ok(users.into_iter().map(|u| (u.id, u.username)).collect())
+}
+
+async fn calculate_balances<'a, A>(db: A, group_id: Uuid) -> AppResult<Vec<Balance>>
+where
+    A: Acquire<'a, Database = Postgres>,
+{
+    let mut conn = db
+        .acquire()
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    let ledger = load_ledger(&mut *conn, group_id).await?;
+
     let members = sqlx::query!(
         "SELECT u.id, u.username
     From users u
@@ -342,89 +1736,88 @@ async fn calculate_balances(pool: &PgPool, group_id: Uuid) -> AppResult<Vec<Bala
     WHERE gm.group_id=$1",
         group_id
     )
-    .fetch_all(pool)
+    .fetch_all(&mut *conn)
     .await
     .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
 
-    let member_count = members.len() as f64;
-    let total_expenses: f64 = expenses
-        .iter()
-        .map(|e| e.amount.to_string().parse::<f64>().unwrap_or(0.0))
-        .sum();
-
-    if member_count == 0.0 {
+    if members.is_empty() {
         return Err(ExpenseError::CalculationError("No members in group".to_string()).into());
     }
 
-    let per_person_share = total_expenses / member_count;
+    let mut net: HashMap<Uuid, Decimal> = HashMap::new();
+    for (participant, payer, amount) in ledger.charges {
+        *net.entry(payer).or_default() += amount;
+        *net.entry(participant).or_default() -= amount;
+    }
+    for (from, to, amount) in ledger.payments {
+        *net.entry(from).or_default() += amount;
+        *net.entry(to).or_default() -= amount;
+    }
 
     let mut balances = Vec::new();
 
-    for member in members {
-        let total_paid: f64 = expenses
-            .iter()
-            .filter(|e| e.paid_by == Some(member.id))
-            .map(|e| e.amount.to_string().parse::<f64>().unwrap_or(0.0))
-            .sum();
-
-        let total_payments_made: f64 = payments
-            .iter()
-            .filter(|p| p.from_user_id == Some(member.id))
-            .map(|p| p.amount.to_string().parse::<f64>().unwrap_or(0.0))
-            .sum();
+    for member in &members {
+        let mut balance = net.remove(&member.id).unwrap_or_default();
+        balance.rescale(2);
 
-        let total_payments_received: f64 = payments
-            .iter()
-            .filter(|p| p.to_user_id == Some(member.id))
-            .map(|p| p.amount.to_string().parse::<f64>().unwrap_or(0.0))
-            .sum();
+        balances.push(Balance {
+            user_id: member.id,
+            username: member.username.clone(),
+            balance,
+        });
+    }
 
-        let balance = total_paid - total_payments_received - per_person_share + total_payments_made;
+    // Members removed with `force` keep whatever they still owe or are owed.
+    let former: Vec<Uuid> = net
+        .iter()
+        .filter(|(_, balance)| !balance.is_zero())
+        .map(|(user_id, _)| *user_id)
+        .collect();
+    let mut former_usernames: Vec<_> = load_usernames(&mut *conn, &former)
+        .await?
+        .into_iter()
+        .collect();
+    former_usernames.sort();
+    for (user_id, username) in former_usernames {
+        let mut balance = net[&user_id];
+        balance.rescale(2);
 
         balances.push(Balance {
-            user_id: member.id,
-            username: member.username,
+            user_id,
+            username,
             balance,
         });
     }
+
+    // Split amounts are allocated to the cent when an expense is created, so
+    // anything other than an exact zero here means the stored data is off.
+    let total: Decimal = balances.iter().map(|b| b.balance).sum();
+    if !total.is_zero() {
+        return Err(ExpenseError::CalculationError(format!(
+            "balances add up to {} instead of 0",
+            total
+        ))
+        .into());
+    }
+
     Ok(balances)
 }
 
 pub async fn make_payment(
     pool: web::Data<PgPool>,
-    path: web::Path<Uuid>,
+    member: GroupMember,
     form: web::Json<CreatePayment>,
-    req: HttpRequest,
 ) -> AppResult<HttpResponse> {
-    let claims = get_user_from_request(&req)?;
-    let user_id = Uuid::from_str(&claims.user_id)
-        .map_err(|_| ValidationError::InvalidFormat("valid UUID".to_string()))?;
-    let group_id = path.into_inner();
+    let (user_id, group_id) = (member.user_id, member.group_id);
 
     form.validate()?;
 
     let to_user_id = Uuid::from_str(&form.to_user_id)
         .map_err(|_| ValidationError::InvalidFormat("valid UUID for to_user_id".to_string()))?;
 
-    let from_member = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM group_members WHERE group_id = $1 AND user_id = $2)",
-    )
-    .bind(group_id)
-    .bind(user_id)
-    .fetch_one(pool.get_ref())
-    .await
-    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
-
-    let to_member = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM group_members WHERE group_id = $1 AND user_id = $2)",
-    )
-    .bind(group_id)
-    .bind(to_user_id)
-    .fetch_one(pool.get_ref())
-    .await
-    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    member.require(GroupRole::Member)?;
 
-    if !from_member || !to_member {
+    if !is_member(pool.get_ref(), group_id, to_user_id).await? {
         return Err(GroupError::NotAMember.into());
     }
 
@@ -434,17 +1827,20 @@ pub async fn make_payment(
         );
     }
 
-    let amount = Decimal::from_f64_retain(form.amount).ok_or(ValidationError::InvalidFormat(
-        "valid decimal number".to_string(),
-    ))?;
+    let amount = form.amount;
+
+    let rates = load_rate_table(pool.get_ref(), group_id).await?;
+    let currency = form.currency.as_deref().unwrap_or(rates.currency());
+    rates.rate(currency, Utc::now().date_naive())?;
 
     sqlx::query!(
-        "INSERT INTO payments (group_id, from_user_id, to_user_id, amount) 
-         VALUES ($1, $2, $3, $4)",
+        "INSERT INTO payments (group_id, from_user_id, to_user_id, amount, currency) 
+         VALUES ($1, $2, $3, $4, $5)",
         group_id,
         user_id,
         to_user_id,
-        amount
+        amount,
+        currency
     )
     .execute(pool.get_ref())
     .await
@@ -452,3 +1848,81 @@ pub async fn make_payment(
 
     Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Payment recorded"})))
 }
+
+pub async fn get_exchange_rates(
+    pool: web::Data<PgPool>,
+    _user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    let rates = sqlx::query_as::<_, ExchangeRate>(
+        "SELECT * FROM exchange_rates ORDER BY base_currency, quote_currency, effective_date DESC",
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(rates))
+}
+
+pub async fn set_exchange_rates(
+    pool: web::Data<PgPool>,
+    form: web::Json<Vec<CreateExchangeRate>>,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+
+    for rate in form.iter() {
+        rate.validate()?;
+    }
+
+    let count = store_exchange_rates(pool.get_ref(), &form).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "imported": count,
+        "message": "Exchange rates saved"
+    })))
+}
+
+pub async fn import_exchange_rates(
+    pool: web::Data<PgPool>,
+    body: String,
+    user: AuthenticatedUser,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+
+    let rates = parse_rates_csv(&body)?;
+    let count = store_exchange_rates(pool.get_ref(), &rates).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "imported": count,
+        "message": "Exchange rates imported"
+    })))
+}
+
+async fn store_exchange_rates(pool: &PgPool, rates: &[CreateExchangeRate]) -> AppResult<usize> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    for rate in rates {
+        sqlx::query(
+            "INSERT INTO exchange_rates (base_currency, quote_currency, rate, effective_date) 
+             VALUES ($1, $2, $3, $4) 
+             ON CONFLICT (base_currency, quote_currency, effective_date) 
+             DO UPDATE SET rate = EXCLUDED.rate",
+        )
+        .bind(&rate.base_currency)
+        .bind(&rate.quote_currency)
+        .bind(rate.rate)
+        .bind(rate.effective_date)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| DatabaseError::QueryFailed(e.to_string()))?;
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| DatabaseError::TransactionFailed(e.to_string()))?;
+
+    Ok(rates.len())
+}
@@ -0,0 +1,217 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+
+use crate::errors::{AppError, AppResult, AuthError};
+
+/// A key tokens can be verified with, and its public half in JWK form.
+/// Shared secrets have no JWK, since they must never be published.
+struct VerificationKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    decoding: DecodingKey,
+    jwk: Option<serde_json::Value>,
+}
+
+/// The keys every token the server issues is signed and verified with.
+///
+/// Tokens are signed with a single current key. Any number of older public
+/// keys can be kept for verification, so rotating the signing key doesn't
+/// invalidate tokens that are still in circulation. Asymmetric keys are
+/// published at `/.well-known/jwks.json`, so other services can verify
+/// tokens without being able to mint them.
+pub struct JwtKeys {
+    signing_kid: Option<String>,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    verification: Vec<VerificationKey>,
+}
+
+impl JwtKeys {
+    /// HS256 with a shared secret: the same secret signs and verifies.
+    pub fn from_secret(secret: &str) -> Self {
+        JwtKeys {
+            signing_kid: None,
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            verification: vec![VerificationKey {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                decoding: DecodingKey::from_secret(secret.as_bytes()),
+                jwk: None,
+            }],
+        }
+    }
+
+    /// Signs with an RSA (RS256) or Ed25519 (EdDSA) private key in PEM form,
+    /// PKCS#8 or, for RSA, PKCS#1. Without an explicit `kid` the key's JWK
+    /// thumbprint is used.
+    pub fn from_private_pem(pem: &str, kid: Option<String>) -> AppResult<Self> {
+        let (algorithm, encoding, public) = if let Some(key) = parse_rsa_private(pem) {
+            let encoding = EncodingKey::from_rsa_pem(pem.as_bytes()).map_err(invalid_key)?;
+            (
+                Algorithm::RS256,
+                encoding,
+                PublicKey::Rsa(key.to_public_key()),
+            )
+        } else if let Ok(key) = ed25519_dalek::SigningKey::from_pkcs8_pem(pem) {
+            let encoding = EncodingKey::from_ed_pem(pem.as_bytes()).map_err(invalid_key)?;
+            (
+                Algorithm::EdDSA,
+                encoding,
+                PublicKey::Ed25519(key.verifying_key()),
+            )
+        } else {
+            return Err(AppError::Internal(
+                "JWT signing key must be an RSA or Ed25519 private key in PEM form".to_string(),
+            ));
+        };
+
+        let key = public.verification_key(kid)?;
+        Ok(JwtKeys {
+            signing_kid: key.kid.clone(),
+            algorithm,
+            encoding,
+            verification: vec![key],
+        })
+    }
+
+    /// Also accepts tokens signed by the private half of an RSA or Ed25519
+    /// public key, typically one that was rotated out.
+    pub fn add_public_pem(&mut self, pem: &str, kid: Option<String>) -> AppResult<()> {
+        let public = if let Some(key) = parse_rsa_public(pem) {
+            PublicKey::Rsa(key)
+        } else if let Ok(key) = ed25519_dalek::VerifyingKey::from_public_key_pem(pem) {
+            PublicKey::Ed25519(key)
+        } else {
+            return Err(AppError::Internal(
+                "JWT verification key must be an RSA or Ed25519 public key in PEM form".to_string(),
+            ));
+        };
+
+        let key = public.verification_key(kid)?;
+        if self
+            .verification
+            .iter()
+            .any(|existing| existing.kid == key.kid)
+        {
+            return Err(AppError::Internal(format!(
+                "Duplicate JWT key id {}",
+                key.kid.unwrap_or_default()
+            )));
+        }
+        self.verification.push(key);
+        Ok(())
+    }
+
+    /// Also accepts HS256 tokens signed with a shared secret. Meant for the
+    /// switch from `JWT_SECRET` to a signing key, until the old tokens have
+    /// run out.
+    pub fn add_secret(&mut self, secret: &str) {
+        self.verification.push(VerificationKey {
+            kid: None,
+            algorithm: Algorithm::HS256,
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            jwk: None,
+        });
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> AppResult<String> {
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.signing_kid.clone();
+
+        jsonwebtoken::encode(&header, claims, &self.encoding)
+            .map_err(|e| AuthError::JwtError(e.to_string()).into())
+    }
+
+    /// Verifies a token with the key its `kid` header names. Tokens without
+    /// a `kid` can only have come from a shared secret.
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> jsonwebtoken::errors::Result<T> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let key = self
+            .verification
+            .iter()
+            .find(|key| key.kid == header.kid && key.algorithm == header.alg)
+            .ok_or(ErrorKind::InvalidToken)?;
+
+        jsonwebtoken::decode::<T>(token, &key.decoding, &Validation::new(key.algorithm))
+            .map(|data| data.claims)
+    }
+
+    /// The public keys as a JWK set.
+    pub fn jwks(&self) -> serde_json::Value {
+        let keys: Vec<&serde_json::Value> = self
+            .verification
+            .iter()
+            .filter_map(|key| key.jwk.as_ref())
+            .collect();
+        serde_json::json!({ "keys": keys })
+    }
+}
+
+enum PublicKey {
+    Rsa(RsaPublicKey),
+    Ed25519(ed25519_dalek::VerifyingKey),
+}
+
+impl PublicKey {
+    fn verification_key(&self, kid: Option<String>) -> AppResult<VerificationKey> {
+        let (algorithm, decoding, mut jwk) = match self {
+            PublicKey::Rsa(key) => {
+                let n = URL_SAFE_NO_PAD.encode(key.n().to_bytes_be());
+                let e = URL_SAFE_NO_PAD.encode(key.e().to_bytes_be());
+                let decoding = DecodingKey::from_rsa_components(&n, &e).map_err(invalid_key)?;
+                // Members in lexicographic order, as RFC 7638 thumbprints need
+                let jwk = serde_json::json!({ "e": e, "kty": "RSA", "n": n });
+                (Algorithm::RS256, decoding, jwk)
+            }
+            PublicKey::Ed25519(key) => {
+                let x = URL_SAFE_NO_PAD.encode(key.to_bytes());
+                let decoding = DecodingKey::from_ed_components(&x).map_err(invalid_key)?;
+                let jwk = serde_json::json!({ "crv": "Ed25519", "kty": "OKP", "x": x });
+                (Algorithm::EdDSA, decoding, jwk)
+            }
+        };
+
+        let kid = kid
+            .unwrap_or_else(|| URL_SAFE_NO_PAD.encode(Sha256::digest(jwk.to_string().as_bytes())));
+        let alg = match algorithm {
+            Algorithm::RS256 => "RS256",
+            _ => "EdDSA",
+        };
+        jwk["kid"] = kid.clone().into();
+        jwk["alg"] = alg.into();
+        jwk["use"] = "sig".into();
+
+        Ok(VerificationKey {
+            kid: Some(kid),
+            algorithm,
+            decoding,
+            jwk: Some(jwk),
+        })
+    }
+}
+
+fn parse_rsa_private(pem: &str) -> Option<RsaPrivateKey> {
+    RsaPrivateKey::from_pkcs8_pem(pem)
+        .ok()
+        .or_else(|| RsaPrivateKey::from_pkcs1_pem(pem).ok())
+}
+
+fn parse_rsa_public(pem: &str) -> Option<RsaPublicKey> {
+    RsaPublicKey::from_public_key_pem(pem)
+        .ok()
+        .or_else(|| RsaPublicKey::from_pkcs1_pem(pem).ok())
+}
+
+fn invalid_key(e: jsonwebtoken::errors::Error) -> AppError {
+    AppError::Internal(format!("Invalid JWT key: {}", e))
+}
@@ -1,8 +1,20 @@
 pub mod auth;
 pub mod authservice;
+pub mod bootstrap;
+pub mod config;
+pub mod currency;
+pub mod database;
 pub mod errors;
+pub mod extractors;
 pub mod handlers;
+pub mod jwtkeys;
+pub mod mailer;
 pub mod models;
+pub mod oidc;
+pub mod ratelimit;
+pub mod settlements;
+pub mod splits;
+pub mod twofactor;
 
 pub use errors::{
     AppError, AppResult, AuthError, DatabaseError, ExpenseError, GroupError, UserError,
@@ -0,0 +1,106 @@
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::errors::{AppError, AppResult};
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers account emails such as password resets and address
+/// verification. Sending blocks, so callers run it off the async runtime.
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> AppResult<()>;
+}
+
+/// Sends mail through an SMTP relay.
+pub struct SmtpMailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, String)>,
+        from: &str,
+    ) -> AppResult<Self> {
+        let mut builder = SmtpTransport::relay(host)
+            .map_err(|e| AppError::Internal(format!("Invalid SMTP host: {}", e)))?
+            .port(port);
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        let from = from
+            .parse()
+            .map_err(|e| AppError::Internal(format!("Invalid sender address: {}", e)))?;
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> AppResult<()> {
+        let to = email
+            .to
+            .parse()
+            .map_err(|e| AppError::Internal(format!("Invalid recipient address: {}", e)))?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&email.subject)
+            .body(email.body.clone())
+            .map_err(|e| AppError::Internal(format!("Failed to build email: {}", e)))?;
+
+        self.transport
+            .send(&message)
+            .map_err(|e| AppError::Internal(format!("Failed to send email: {}", e)))?;
+        Ok(())
+    }
+}
+
+/// Writes mail to the log, and to an outbox file when one is configured,
+/// instead of sending it. Meant for development and tests.
+pub struct LogMailer {
+    outbox: Option<PathBuf>,
+}
+
+impl LogMailer {
+    pub fn new(outbox: Option<PathBuf>) -> Self {
+        LogMailer { outbox }
+    }
+}
+
+impl Mailer for LogMailer {
+    fn send(&self, email: &Email) -> AppResult<()> {
+        log::info!("Email to {}: {}", email.to, email.subject);
+
+        if let Some(path) = &self.outbox {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| AppError::Internal(format!("Failed to open outbox: {}", e)))?;
+            writeln!(
+                file,
+                "To: {}\nSubject: {}\n\n{}\n",
+                email.to, email.subject, email.body
+            )
+            .map_err(|e| AppError::Internal(format!("Failed to write outbox: {}", e)))?;
+        }
+        Ok(())
+    }
+}
@@ -1,43 +1,115 @@
 use actix_cors::Cors;
 use actix_web::{middleware::Logger, web, App, HttpServer};
 use dotenv::dotenv;
-use sqlx::postgres::PgPoolOptions;
-use std::env;
+use std::io::Write;
+use std::sync::Arc;
 
+use expenses_backend::auth::configure_password_hashing;
+use expenses_backend::config::{
+    AppConfig, CorsConfig, JwtConfig, LogFormat, MailConfig, MailTransport,
+};
+use expenses_backend::bootstrap::{bootstrap_admin, check_default_credentials};
+use expenses_backend::database::{create_pool, run_migrations};
 use expenses_backend::errors::AppError;
+use expenses_backend::jwtkeys::JwtKeys;
+use expenses_backend::mailer::{LogMailer, Mailer, SmtpMailer};
+use expenses_backend::oidc::OidcClient;
+use expenses_backend::ratelimit::{InMemoryAttemptStore, LoginThrottle};
 
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
-    env_logger::init();
+    let config = AppConfig::load()?;
+    init_logging(config.log.format);
 
-    let database_url = env::var("DATABASE_URL")
-        .map_err(|_| AppError::Internal("DATABASE_URL must be set".to_string()))?;
+    let jwt_keys = web::Data::new(load_jwt_keys(&config.jwt)?);
+    configure_password_hashing(config.password_hashing)?;
+    let throttle = web::Data::new(LoginThrottle::new(
+        Arc::new(InMemoryAttemptStore::new()),
+        &config.throttle,
+    ));
+    let mailer: web::Data<dyn Mailer> = web::Data::from(build_mailer(&config.mail)?);
+    let oidc = web::Data::new(
+        config
+            .oidc
+            .enabled()
+            .then(|| OidcClient::new(config.oidc.clone()))
+            .transpose()?,
+    );
 
-    let _jwt_secret = env::var("JWT_SECRET")
-        .map_err(|_| AppError::Internal("JWT_SECRET must be set".to_string()))?;
+    let pool = create_pool(&config.database).await?;
+    if config.database.run_migrations {
+        run_migrations(&pool).await?;
+    }
+    check_default_credentials(&pool).await?;
+    bootstrap_admin(&pool, &config.admin).await?;
 
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
-        .await
-        .map_err(|e| AppError::Internal(format!("Failed to connect to database: {}", e)))?;
+    let bind_address = config.server.bind_address.clone();
+    let config = web::Data::new(config);
 
     HttpServer::new(move || {
-        let cors = Cors::default()
-            .allow_any_origin()
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
+        let cors = cors(&config.cors)
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"])
             .allowed_headers(vec!["Content-Type", "Authorization"])
             .supports_credentials();
 
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(jwt_keys.clone())
+            .app_data(throttle.clone())
+            .app_data(config.clone())
+            .app_data(mailer.clone())
+            .app_data(oidc.clone())
             .wrap(cors)
             .wrap(Logger::default())
+            .route(
+                "/.well-known/jwks.json",
+                web::get().to(expenses_backend::handlers::jwks),
+            )
             .route(
                 "/api/auth/login",
                 web::post().to(expenses_backend::handlers::login),
             )
+            .route(
+                "/api/auth/login/2fa",
+                web::post().to(expenses_backend::handlers::complete_two_factor_login),
+            )
+            .route(
+                "/api/auth/oidc/authorize",
+                web::get().to(expenses_backend::handlers::begin_oidc_login),
+            )
+            .route(
+                "/api/auth/oidc/callback",
+                web::post().to(expenses_backend::handlers::complete_oidc_login),
+            )
+            .route(
+                "/api/auth/refresh",
+                web::post().to(expenses_backend::handlers::refresh_token),
+            )
+            .route(
+                "/api/auth/logout",
+                web::post().to(expenses_backend::handlers::logout),
+            )
+            .route(
+                "/api/auth/password-reset/request",
+                web::post().to(expenses_backend::handlers::request_password_reset),
+            )
+            .route(
+                "/api/auth/password-reset/confirm",
+                web::post().to(expenses_backend::handlers::confirm_password_reset),
+            )
+            .route(
+                "/api/auth/verify-email/request",
+                web::post().to(expenses_backend::handlers::request_email_verification),
+            )
+            .route(
+                "/api/auth/verify-email/resend",
+                web::post().to(expenses_backend::handlers::resend_email_verification),
+            )
+            .route(
+                "/api/auth/verify-email/confirm",
+                web::post().to(expenses_backend::handlers::confirm_email_verification),
+            )
             .route(
                 "/api/auth/register",
                 web::post().to(expenses_backend::handlers::register),
@@ -50,6 +122,42 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "/api/users",
                 web::get().to(expenses_backend::handlers::get_users),
             )
+            .route(
+                "/api/users/me",
+                web::get().to(expenses_backend::handlers::get_current_user),
+            )
+            .route(
+                "/api/users/me",
+                web::patch().to(expenses_backend::handlers::update_current_user),
+            )
+            .route(
+                "/api/users/me/password",
+                web::post().to(expenses_backend::handlers::change_password),
+            )
+            .route(
+                "/api/users/me/tokens",
+                web::post().to(expenses_backend::handlers::create_access_token),
+            )
+            .route(
+                "/api/users/me/tokens",
+                web::get().to(expenses_backend::handlers::get_access_tokens),
+            )
+            .route(
+                "/api/users/me/tokens/{token_id}",
+                web::delete().to(expenses_backend::handlers::revoke_access_token),
+            )
+            .route(
+                "/api/users/me/2fa/enroll",
+                web::post().to(expenses_backend::handlers::begin_two_factor_enrollment),
+            )
+            .route(
+                "/api/users/me/2fa/verify",
+                web::post().to(expenses_backend::handlers::enable_two_factor),
+            )
+            .route(
+                "/api/users/me/2fa/disable",
+                web::post().to(expenses_backend::handlers::disable_two_factor),
+            )
             .route(
                 "/api/groups",
                 web::post().to(expenses_backend::handlers::create_group),
@@ -58,6 +166,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "/api/groups",
                 web::get().to(expenses_backend::handlers::get_user_groups),
             )
+            .route(
+                "/api/groups/{group_id}/members",
+                web::post().to(expenses_backend::handlers::add_group_member),
+            )
+            .route(
+                "/api/groups/{group_id}/members/{user_id}",
+                web::put().to(expenses_backend::handlers::update_member_role),
+            )
+            .route(
+                "/api/groups/{group_id}/members/{user_id}",
+                web::delete().to(expenses_backend::handlers::remove_group_member),
+            )
+            .route(
+                "/api/groups/{group_id}/leave",
+                web::post().to(expenses_backend::handlers::leave_group),
+            )
+            .route(
+                "/api/groups/{group_id}/invitations",
+                web::post().to(expenses_backend::handlers::create_invitation),
+            )
+            .route(
+                "/api/groups/{group_id}/invitations",
+                web::get().to(expenses_backend::handlers::get_group_invitations),
+            )
+            .route(
+                "/api/groups/{group_id}/invitations/{invitation_id}",
+                web::delete().to(expenses_backend::handlers::revoke_invitation),
+            )
+            .route(
+                "/api/invitations/{token}/accept",
+                web::post().to(expenses_backend::handlers::accept_invitation),
+            )
             .route(
                 "/api/groups/{group_id}/expenses",
                 web::get().to(expenses_backend::handlers::get_group_expenses),
@@ -66,18 +206,123 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "/api/groups/{group_id}/expenses",
                 web::post().to(expenses_backend::handlers::add_expense),
             )
+            .route(
+                "/api/groups/{group_id}/expenses/{expense_id}",
+                web::put().to(expenses_backend::handlers::update_expense),
+            )
+            .route(
+                "/api/groups/{group_id}/expenses/{expense_id}",
+                web::delete().to(expenses_backend::handlers::delete_expense),
+            )
             .route(
                 "/api/groups/{group_id}/balances",
                 web::get().to(expenses_backend::handlers::get_group_balances),
             )
+            .route(
+                "/api/groups/{group_id}/settlements",
+                web::get().to(expenses_backend::handlers::get_group_settlements),
+            )
             .route(
                 "/api/groups/{group_id}/payments",
                 web::post().to(expenses_backend::handlers::make_payment),
             )
+            .route(
+                "/api/exchange-rates",
+                web::get().to(expenses_backend::handlers::get_exchange_rates),
+            )
+            .route(
+                "/api/exchange-rates",
+                web::post().to(expenses_backend::handlers::set_exchange_rates),
+            )
+            .route(
+                "/api/exchange-rates/import",
+                web::post().to(expenses_backend::handlers::import_exchange_rates),
+            )
     })
-    .bind("0.0.0.0:8080")?
+    .bind(bind_address)?
     .run()
     .await?;
 
     Ok(())
 }
+
+/// Sets up env_logger, with levels from `RUST_LOG` as before. The JSON format
+/// writes one object per line with the timestamp, level, target and message.
+fn init_logging(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    builder.init();
+}
+
+fn cors(config: &CorsConfig) -> Cors {
+    if config.allowed_origins.iter().any(|origin| origin == "*") {
+        return Cors::default().allow_any_origin();
+    }
+    config
+        .allowed_origins
+        .iter()
+        .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+}
+
+/// Loads the token signing keys described by `config`.
+fn load_jwt_keys(config: &JwtConfig) -> Result<JwtKeys, AppError> {
+    let read_pem = |path: &std::path::Path| {
+        std::fs::read_to_string(path).map_err(|e| {
+            AppError::Internal(format!("Failed to read key {}: {}", path.display(), e))
+        })
+    };
+
+    let Some(signing_key) = &config.signing_key else {
+        let secret = config.secret.as_deref().ok_or_else(|| {
+            AppError::Internal("JWT_SECRET or JWT_SIGNING_KEY must be set".to_string())
+        })?;
+        return Ok(JwtKeys::from_secret(secret));
+    };
+
+    let mut keys = JwtKeys::from_private_pem(&read_pem(signing_key)?, config.key_id.clone())?;
+    for entry in &config.verification_keys {
+        let (kid, path) = match entry.split_once('=') {
+            Some((kid, path)) => (Some(kid.to_string()), path),
+            None => (None, entry.as_str()),
+        };
+        keys.add_public_pem(&read_pem(path.as_ref())?, kid)?;
+    }
+    if let Some(secret) = &config.secret {
+        log::warn!("JWT_SECRET is set alongside JWT_SIGNING_KEY; HS256 tokens are still accepted");
+        keys.add_secret(secret);
+    }
+    Ok(keys)
+}
+
+/// Picks the mail transport: `smtp` sends through the configured relay,
+/// `log` only logs mail (optionally to the outbox file).
+fn build_mailer(config: &MailConfig) -> Result<Arc<dyn Mailer>, AppError> {
+    if config.transport == MailTransport::Log {
+        return Ok(Arc::new(LogMailer::new(config.outbox.clone())));
+    }
+
+    let host = config
+        .smtp_host
+        .as_deref()
+        .ok_or_else(|| AppError::Internal("SMTP_HOST must be set".to_string()))?;
+    let from = config
+        .from
+        .as_deref()
+        .ok_or_else(|| AppError::Internal("MAIL_FROM must be set".to_string()))?;
+    let credentials = match (&config.smtp_username, &config.smtp_password) {
+        (Some(username), Some(password)) => Some((username.clone(), password.clone())),
+        _ => None,
+    };
+
+    Ok(Arc::new(SmtpMailer::new(host, config.smtp_port, credentials, from)?))
+}
@@ -1,10 +1,14 @@
-use chrono::{DateTime, Utc};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
-use crate::errors::{AppResult, ValidationError};
+use crate::currency::validate_currency_code;
+use crate::errors::{AppError, AppResult, GroupError, ValidationError};
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct User {
@@ -12,8 +16,9 @@ pub struct User {
     pub email: String,
     pub username: String,
     pub password_hash: String,
-    pub is_admin: Option<bool>,
-    pub created_at: Option<DateTime<Utc>>,
+    pub is_admin: bool,
+    pub created_at: DateTime<Utc>,
+    pub email_verified_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,6 +27,26 @@ pub struct UserResponse {
     pub email: String,
     pub username: String,
     pub is_admin: bool,
+    pub email_verified: bool,
+}
+
+/// Longest values the corresponding columns hold, in characters.
+pub const MAX_EMAIL_LENGTH: usize = 255;
+pub const MAX_USERNAME_LENGTH: usize = 100;
+pub const MAX_GROUP_NAME_LENGTH: usize = 255;
+pub const MAX_DESCRIPTION_LENGTH: usize = 500;
+pub const MAX_ACCESS_TOKEN_NAME_LENGTH: usize = 100;
+pub const MAX_CATEGORY_LENGTH: usize = 50;
+
+fn validate_length(value: &str, max: usize, field: &str) -> AppResult<()> {
+    if value.chars().count() > max {
+        return Err(ValidationError::InvalidLength(format!(
+            "{} must be at most {} characters",
+            field, max
+        ))
+        .into());
+    }
+    Ok(())
 }
 
 #[derive(Debug, Deserialize)]
@@ -51,6 +76,60 @@ impl CreateUser {
         if !self.email.contains('@') {
             return Err(ValidationError::InvalidFormat("valid email address".to_string()).into());
         }
+        validate_length(&self.email, MAX_EMAIL_LENGTH, "email")?;
+        validate_length(&self.username, MAX_USERNAME_LENGTH, "username")?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateProfile {
+    pub username: Option<String>,
+    pub email: Option<String>,
+}
+
+impl UpdateProfile {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.username.is_none() && self.email.is_none() {
+            return Err(ValidationError::RequiredField("username or email".to_string()).into());
+        }
+        if self.username.as_deref().is_some_and(str::is_empty) {
+            return Err(ValidationError::RequiredField("username".to_string()).into());
+        }
+        if self
+            .email
+            .as_deref()
+            .is_some_and(|email| !email.contains('@'))
+        {
+            return Err(ValidationError::InvalidFormat("valid email address".to_string()).into());
+        }
+        if let Some(email) = &self.email {
+            validate_length(email, MAX_EMAIL_LENGTH, "email")?;
+        }
+        if let Some(username) = &self.username {
+            validate_length(username, MAX_USERNAME_LENGTH, "username")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+impl ChangePassword {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.current_password.is_empty() {
+            return Err(ValidationError::RequiredField("current_password".to_string()).into());
+        }
+        if self.new_password.len() < 8 {
+            return Err(ValidationError::InvalidFormat(
+                "password must be at least 8 characters".to_string(),
+            )
+            .into());
+        }
         Ok(())
     }
 }
@@ -73,18 +152,262 @@ impl LoginRequest {
     }
 }
 
+/// What a mailed single-use token may be redeemed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum TokenPurpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+impl PasswordResetRequest {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.email.is_empty() {
+            return Err(ValidationError::RequiredField("email".to_string()).into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+impl ResendVerificationRequest {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.email.is_empty() {
+            return Err(ValidationError::RequiredField("email".to_string()).into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetConfirm {
+    pub token: String,
+    pub new_password: String,
+}
+
+impl PasswordResetConfirm {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.token.is_empty() {
+            return Err(ValidationError::RequiredField("token".to_string()).into());
+        }
+        if self.new_password.len() < 8 {
+            return Err(ValidationError::InvalidFormat(
+                "password must be at least 8 characters".to_string(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailVerificationConfirm {
+    pub token: String,
+}
+
+impl EmailVerificationConfirm {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.token.is_empty() {
+            return Err(ValidationError::RequiredField("token".to_string()).into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+impl RefreshRequest {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.refresh_token.is_empty() {
+            return Err(ValidationError::RequiredField("refresh_token".to_string()).into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCode {
+    pub code: String,
+}
+
+impl TwoFactorCode {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.code.trim().is_empty() {
+            return Err(ValidationError::RequiredField("code".to_string()).into());
+        }
+        Ok(())
+    }
+}
+
+/// Second step of a login with two-factor authentication. `code` is either
+/// a code from the authenticator app or one of the recovery codes.
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLogin {
+    pub challenge_token: String,
+    pub code: String,
+}
+
+impl TwoFactorLogin {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.challenge_token.is_empty() || self.code.trim().is_empty() {
+            return Err(
+                ValidationError::RequiredField("challenge_token and code".to_string()).into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorEnrollment {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Debug, FromRow)]
+pub struct UserTotp {
+    pub user_id: Uuid,
+    pub secret: String,
+    pub enabled_at: Option<DateTime<Utc>>,
+    pub last_used_step: Option<i64>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// What the identity provider sent the user back to the web app with.
+#[derive(Debug, Deserialize)]
+pub struct OidcCallback {
+    pub code: String,
+    pub state: String,
+}
+
+impl OidcCallback {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.code.is_empty() || self.state.is_empty() {
+            return Err(ValidationError::RequiredField("code and state".to_string()).into());
+        }
+        Ok(())
+    }
+}
+
+/// A freshly issued access token and the refresh token that can renew it.
+#[derive(Debug, Serialize)]
+pub struct AuthTokens {
+    pub token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(Debug, FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub family_id: Uuid,
+    pub refresh_token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// What a personal access token may do. Read-only tokens are limited to
+/// `GET` requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum TokenScope {
+    #[default]
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct PersonalAccessToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub token_prefix: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub scope: TokenScope,
+    pub group_ids: Option<Vec<Uuid>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// A newly created access token. The token itself is only ever returned
+/// here.
+#[derive(Debug, Serialize)]
+pub struct PersonalAccessTokenResponse {
+    #[serde(flatten)]
+    pub access_token: PersonalAccessToken,
+    pub token: String,
+}
+
+/// Longest a personal access token may stay valid, in days.
+pub const MAX_ACCESS_TOKEN_DAYS: i64 = 365;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAccessToken {
+    pub name: String,
+    #[serde(default)]
+    pub scope: TokenScope,
+    pub group_ids: Option<Vec<Uuid>>,
+    pub expires_in_days: Option<i64>,
+}
+
+impl CreateAccessToken {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.name.trim().is_empty() {
+            return Err(ValidationError::RequiredField("name".to_string()).into());
+        }
+        validate_length(&self.name, MAX_ACCESS_TOKEN_NAME_LENGTH, "name")?;
+        if self.group_ids.as_ref().is_some_and(|ids| ids.is_empty()) {
+            return Err(ValidationError::InvalidFormat(
+                "group_ids must name at least one group".to_string(),
+            )
+            .into());
+        }
+        if matches!(self.expires_in_days, Some(days) if !(1..=MAX_ACCESS_TOKEN_DAYS).contains(&days))
+        {
+            return Err(ValidationError::InvalidRange(format!(
+                "expires_in_days must be between 1 and {}",
+                MAX_ACCESS_TOKEN_DAYS
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Group {
     pub id: Uuid,
     pub name: String,
     pub created_by: Uuid,
     pub created_at: DateTime<Utc>,
+    pub currency: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateGroup {
     pub name: String,
+    #[serde(default)]
     pub user_ids: Vec<Uuid>,
+    pub currency: Option<String>,
 }
 
 impl CreateGroup {
@@ -92,19 +415,55 @@ impl CreateGroup {
         if self.name.is_empty() {
             return Err(ValidationError::RequiredField("group name".to_string()).into());
         }
-        if self.user_ids.is_empty() {
-            return Err(ValidationError::RequiredField("at least one user ID".to_string()).into());
+        validate_length(&self.name, MAX_GROUP_NAME_LENGTH, "group name")?;
+        if let Some(currency) = &self.currency {
+            validate_currency_code(currency)?;
         }
-        if self.name.len() > 255 {
-            return Err(ValidationError::InvalidFormat(
-                "group name must be less than 255 characters".to_string(),
-            )
-            .into());
+        Ok(())
+    }
+}
+
+/// A member's role within one group, from least to most privileged.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum GroupRole {
+    Viewer,
+    #[default]
+    Member,
+    Manager,
+    Owner,
+}
+
+impl GroupRole {
+    pub fn require(self, required: GroupRole) -> AppResult<()> {
+        if self < required {
+            return Err(GroupError::InsufficientPermissions.into());
         }
         Ok(())
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct AddMember {
+    pub user_id: Uuid,
+    #[serde(default)]
+    pub role: GroupRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateMemberRole {
+    pub role: GroupRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemoveMemberQuery {
+    #[serde(default)]
+    pub force: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Expense {
     pub id: Uuid,
@@ -113,6 +472,39 @@ pub struct Expense {
     pub amount: Decimal,
     pub description: String,
     pub created_at: DateTime<Utc>,
+    pub split_type: SplitType,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub currency: String,
+    pub category: Option<String>,
+    pub incurred_on: NaiveDate,
+    pub incurred_at: Option<DateTime<Utc>>,
+    pub time_zone: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum SplitType {
+    #[default]
+    Equal,
+    Exact,
+    Percentage,
+    Shares,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ExpenseSplit {
+    pub expense_id: Uuid,
+    pub user_id: Uuid,
+    pub amount: Decimal,
+    pub share: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExpenseWithSplits {
+    #[serde(flatten)]
+    pub expense: Expense,
+    pub splits: Vec<ExpenseSplit>,
 }
 
 #[derive(Debug, Serialize)]
@@ -121,39 +513,344 @@ pub struct ExpenseResponse {
     pub group_id: Uuid,
     pub paid_by: Uuid,
     pub amount: Decimal,
+    pub currency: String,
+    pub converted_amount: Decimal,
+    pub exchange_rate: Decimal,
     pub description: String,
+    pub category: Option<String>,
+    pub incurred_on: NaiveDate,
+    pub incurred_at: Option<DateTime<Utc>>,
+    pub time_zone: Option<String>,
     pub created_at: DateTime<Utc>,
     pub username: String,
 }
 
+/// One page of a group's expenses and where the next one starts.
+#[derive(Debug, Serialize)]
+pub struct ExpensePage {
+    pub expenses: Vec<ExpenseResponse>,
+    pub pagination: PageInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageInfo {
+    pub limit: i64,
+    /// Expenses matching the filters across all pages.
+    pub total: i64,
+    pub has_more: bool,
+    /// Pass as `cursor` to fetch the next page; absent on the last one.
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpenseSortField {
+    #[default]
+    IncurredOn,
+    CreatedAt,
+    Amount,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+pub const DEFAULT_EXPENSE_PAGE_SIZE: i64 = 50;
+pub const MAX_EXPENSE_PAGE_SIZE: i64 = 100;
+
+/// Filters and paging for the expense list. `from` and `to` are compared with
+/// the day each expense was incurred and both ends of a range are inclusive;
+/// `q` matches anywhere in the description.
+#[derive(Debug, Deserialize)]
+pub struct ExpenseListQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub paid_by: Option<Uuid>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub min_amount: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+    pub q: Option<String>,
+    pub category: Option<String>,
+    #[serde(default)]
+    pub sort: ExpenseSortField,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+impl ExpenseListQuery {
+    pub fn validate(&self) -> AppResult<()> {
+        if let Some(limit) = self.limit {
+            if !(1..=MAX_EXPENSE_PAGE_SIZE).contains(&limit) {
+                return Err(ValidationError::InvalidRange(format!(
+                    "limit must be between 1 and {}",
+                    MAX_EXPENSE_PAGE_SIZE
+                ))
+                .into());
+            }
+        }
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from > to {
+                return Err(
+                    ValidationError::InvalidRange("from must not be after to".to_string()).into(),
+                );
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_amount, self.max_amount) {
+            if min > max {
+                return Err(ValidationError::InvalidRange(
+                    "min_amount must not be greater than max_amount".to_string(),
+                )
+                .into());
+            }
+        }
+        if let Some(q) = &self.q {
+            validate_length(q, MAX_DESCRIPTION_LENGTH, "q")?;
+        }
+        if let Some(category) = &self.category {
+            validate_length(category, MAX_CATEGORY_LENGTH, "category")?;
+        }
+        Ok(())
+    }
+
+    pub fn page_size(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_EXPENSE_PAGE_SIZE)
+    }
+
+    /// Reads the `cursor` parameter, which must come from a page listed in
+    /// the same order.
+    pub fn decode_cursor(&self) -> AppResult<Option<ExpenseCursor>> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(None);
+        };
+        let invalid =
+            || ValidationError::InvalidFormat("cursor from the previous page".to_string());
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let cursor: ExpenseCursor = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+        if cursor.sort != self.sort || cursor.order != self.order {
+            return Err(invalid().into());
+        }
+        Ok(Some(cursor))
+    }
+}
+
+/// Position of the last expense on a page. It carries every sort key, so the
+/// next page can resume right after it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExpenseCursor {
+    pub sort: ExpenseSortField,
+    pub order: SortOrder,
+    pub incurred_on: NaiveDate,
+    pub created_at: DateTime<Utc>,
+    pub amount: Decimal,
+    pub id: Uuid,
+}
+
+impl ExpenseCursor {
+    pub fn encode(&self) -> AppResult<String> {
+        let json = serde_json::to_vec(self)
+            .map_err(|e| AppError::Internal(format!("Failed to encode cursor: {}", e)))?;
+        Ok(URL_SAFE_NO_PAD.encode(json))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CreateExpense {
-    pub amount: f64,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub amount: Decimal,
+    pub currency: Option<String>,
     pub description: String,
+    pub category: Option<String>,
+    /// Defaults to today where the payer is, or in UTC without a
+    /// `time_zone`. Leaving out all of the date fields when updating keeps
+    /// the expense's current date.
+    pub incurred_on: Option<NaiveDate>,
+    /// Local time on `incurred_on`, read as UTC without a `time_zone`.
+    pub incurred_time: Option<NaiveTime>,
+    /// IANA name of the payer's time zone, such as `Europe/Paris`.
+    pub time_zone: Option<String>,
+    #[serde(default)]
+    pub split_type: SplitType,
+    #[serde(default)]
+    pub splits: Vec<SplitEntry>,
+    pub participants: Option<Vec<Uuid>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SplitEntry {
+    pub user_id: Uuid,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub value: Decimal,
+}
+
+const MAX_AMOUNT: Decimal = Decimal::from_parts(99999999, 0, 0, false, 2);
+
+fn validate_amount(amount: Decimal, field: &str) -> AppResult<()> {
+    if amount <= Decimal::ZERO {
+        return Err(
+            ValidationError::InvalidFormat(format!("{} must be greater than 0", field)).into(),
+        );
+    }
+    if amount > MAX_AMOUNT {
+        return Err(ValidationError::InvalidFormat(format!(
+            "{} must be less than 1,000,000",
+            field
+        ))
+        .into());
+    }
+    if amount.scale() > 2 {
+        return Err(ValidationError::InvalidFormat(format!(
+            "{} with at most 2 decimal places",
+            field
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// When an expense happened: the calendar date where it was paid, and the
+/// exact moment if a time was given.
+#[derive(Debug, Clone)]
+pub struct ExpenseDate {
+    pub incurred_on: NaiveDate,
+    pub incurred_at: Option<DateTime<Utc>>,
+    pub time_zone: Option<String>,
+}
+
+impl ExpenseDate {
+    pub fn of(expense: &Expense) -> Self {
+        ExpenseDate {
+            incurred_on: expense.incurred_on,
+            incurred_at: expense.incurred_at,
+            time_zone: expense.time_zone.clone(),
+        }
+    }
 }
 
 impl CreateExpense {
+    /// Works out when the expense happened, given the expense being updated
+    /// if there is one. Dates after today where the payer is are refused.
+    /// Without a `time_zone` that place is unknown, so up to one day after
+    /// today in UTC is allowed: the payer may be in a zone that is already a
+    /// day ahead of it.
+    pub fn expense_date(&self, existing: Option<&Expense>) -> AppResult<ExpenseDate> {
+        if let Some(expense) = existing {
+            if self.incurred_on.is_none()
+                && self.incurred_time.is_none()
+                && self.time_zone.is_none()
+            {
+                return Ok(ExpenseDate::of(expense));
+            }
+        }
+
+        let zone = match &self.time_zone {
+            Some(name) => name.parse::<Tz>().map_err(|_| {
+                ValidationError::InvalidFormat(
+                    "time_zone as an IANA name such as Europe/London".to_string(),
+                )
+            })?,
+            None => Tz::UTC,
+        };
+        let today = Utc::now().with_timezone(&zone).date_naive();
+        let latest = match self.time_zone {
+            Some(_) => today,
+            None => today.succ_opt().unwrap_or(today),
+        };
+
+        let incurred_on = self
+            .incurred_on
+            .or(existing.map(|e| e.incurred_on))
+            .unwrap_or(today);
+        if incurred_on > latest {
+            return Err(ValidationError::InvalidRange(
+                "incurred_on must not be in the future".to_string(),
+            )
+            .into());
+        }
+
+        let incurred_at = match self.incurred_time {
+            Some(time) => {
+                let local = zone
+                    .from_local_datetime(&incurred_on.and_time(time))
+                    .earliest()
+                    .ok_or_else(|| {
+                        ValidationError::InvalidFormat(
+                            "incurred_time that exists on that day in the time zone".to_string(),
+                        )
+                    })?;
+                Some(local.with_timezone(&Utc))
+            }
+            None => None,
+        };
+
+        Ok(ExpenseDate {
+            incurred_on,
+            incurred_at,
+            time_zone: self.time_zone.clone(),
+        })
+    }
+
     pub fn validate(&self) -> AppResult<()> {
         if self.description.is_empty() {
             return Err(ValidationError::RequiredField("description".to_string()).into());
         }
-        if self.amount <= 0.0 {
-            return Err(ValidationError::InvalidFormat(
-                "amount must be greater than 0".to_string(),
-            )
-            .into());
+        validate_amount(self.amount, "amount")?;
+        if let Some(currency) = &self.currency {
+            validate_currency_code(currency)?;
         }
-        if self.amount > 999999.99 {
-            return Err(ValidationError::InvalidFormat(
-                "amount must be less than 1,000,000".to_string(),
-            )
-            .into());
+        validate_length(&self.description, MAX_DESCRIPTION_LENGTH, "description")?;
+        if let Some(category) = &self.category {
+            if category.trim().is_empty() {
+                return Err(ValidationError::RequiredField("category".to_string()).into());
+            }
+            validate_length(category, MAX_CATEGORY_LENGTH, "category")?;
         }
-        if self.description.len() > 500 {
-            return Err(ValidationError::InvalidFormat(
-                "description must be less than 500 characters".to_string(),
-            )
-            .into());
+        match self.split_type {
+            SplitType::Equal if !self.splits.is_empty() => {
+                return Err(ValidationError::InvalidFormat(
+                    "no splits for an equal split".to_string(),
+                )
+                .into());
+            }
+            SplitType::Exact | SplitType::Percentage | SplitType::Shares
+                if self.splits.is_empty() =>
+            {
+                return Err(ValidationError::RequiredField("splits".to_string()).into());
+            }
+            _ => {}
+        }
+        if let Some(participants) = &self.participants {
+            if participants.is_empty() {
+                return Err(
+                    ValidationError::RequiredField("at least one participant".to_string()).into(),
+                );
+            }
+            for (i, participant) in participants.iter().enumerate() {
+                if participants[..i].contains(participant) {
+                    return Err(ValidationError::InvalidFormat(
+                        "each participant listed once".to_string(),
+                    )
+                    .into());
+                }
+            }
+        }
+        for (i, split) in self.splits.iter().enumerate() {
+            if split.value <= Decimal::ZERO {
+                return Err(ValidationError::InvalidRange(
+                    "split values must be greater than 0".to_string(),
+                )
+                .into());
+            }
+            if self.splits[..i].iter().any(|s| s.user_id == split.user_id) {
+                return Err(ValidationError::InvalidFormat(
+                    "each user at most once in splits".to_string(),
+                )
+                .into());
+            }
         }
         Ok(())
     }
@@ -163,13 +860,34 @@ impl CreateExpense {
 pub struct Balance {
     pub user_id: Uuid,
     pub username: String,
-    pub balance: f64,
+    pub balance: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Settlement {
+    pub from_user_id: Uuid,
+    pub from_username: String,
+    pub to_user_id: Uuid,
+    pub to_username: String,
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SettlementQuery {
+    #[serde(default = "default_simplify")]
+    pub simplify: bool,
+}
+
+fn default_simplify() -> bool {
+    true
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreatePayment {
     pub to_user_id: String,
-    pub amount: f64,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub amount: Decimal,
+    pub currency: Option<String>,
 }
 
 impl CreatePayment {
@@ -177,17 +895,9 @@ impl CreatePayment {
         if self.to_user_id.is_empty() {
             return Err(ValidationError::RequiredField("to_user_id".to_string()).into());
         }
-        if self.amount <= 0.0 {
-            return Err(ValidationError::InvalidFormat(
-                "payment amount must be greater than 0".to_string(),
-            )
-            .into());
-        }
-        if self.amount > 999999.99 {
-            return Err(ValidationError::InvalidFormat(
-                "payment amount must be less than 1,000,000".to_string(),
-            )
-            .into());
+        validate_amount(self.amount, "payment amount")?;
+        if let Some(currency) = &self.currency {
+            validate_currency_code(currency)?;
         }
         uuid::Uuid::parse_str(&self.to_user_id)
             .map_err(|_| ValidationError::InvalidFormat("valid UUID for to_user_id".to_string()))?;
@@ -203,4 +913,299 @@ pub struct Payment {
     pub to_user_id: Uuid,
     pub amount: Decimal,
     pub created_at: DateTime<Utc>,
+    pub currency: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct GroupInvitation {
+    pub id: Uuid,
+    pub group_id: Uuid,
+    pub created_by: Uuid,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvitationResponse {
+    #[serde(flatten)]
+    pub invitation: GroupInvitation,
+    pub token: String,
+}
+
+/// Longest an invitation link may stay valid, in hours.
+pub const MAX_INVITATION_HOURS: i64 = 30 * 24;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInvitation {
+    #[serde(default = "default_invitation_hours")]
+    pub expires_in_hours: i64,
+    pub max_uses: Option<i32>,
+}
+
+fn default_invitation_hours() -> i64 {
+    7 * 24
+}
+
+impl CreateInvitation {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.expires_in_hours < 1 || self.expires_in_hours > MAX_INVITATION_HOURS {
+            return Err(ValidationError::InvalidRange(format!(
+                "expires_in_hours must be between 1 and {}",
+                MAX_INVITATION_HOURS
+            ))
+            .into());
+        }
+        if matches!(self.max_uses, Some(uses) if uses < 1) {
+            return Err(
+                ValidationError::InvalidRange("max_uses must be at least 1".to_string()).into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ExchangeRate {
+    pub base_currency: String,
+    pub quote_currency: String,
+    pub rate: Decimal,
+    pub effective_date: NaiveDate,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateExchangeRate {
+    pub base_currency: String,
+    pub quote_currency: String,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub rate: Decimal,
+    pub effective_date: NaiveDate,
+}
+
+impl CreateExchangeRate {
+    pub fn validate(&self) -> AppResult<()> {
+        validate_currency_code(&self.base_currency)?;
+        validate_currency_code(&self.quote_currency)?;
+        if self.base_currency == self.quote_currency {
+            return Err(ValidationError::InvalidFormat(
+                "different base and quote currencies".to_string(),
+            )
+            .into());
+        }
+        if self.rate <= Decimal::ZERO {
+            return Err(
+                ValidationError::InvalidRange("rate must be greater than 0".to_string()).into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list_query(cursor: Option<String>) -> ExpenseListQuery {
+        ExpenseListQuery {
+            cursor,
+            limit: None,
+            paid_by: None,
+            from: None,
+            to: None,
+            min_amount: None,
+            max_amount: None,
+            q: None,
+            category: None,
+            sort: ExpenseSortField::Amount,
+            order: SortOrder::Asc,
+        }
+    }
+
+    fn cursor() -> ExpenseCursor {
+        ExpenseCursor {
+            sort: ExpenseSortField::Amount,
+            order: SortOrder::Asc,
+            incurred_on: NaiveDate::from_ymd_opt(2025, 3, 14).unwrap(),
+            created_at: Utc.with_ymd_and_hms(2025, 3, 14, 9, 26, 53).unwrap(),
+            amount: "12.34".parse().unwrap(),
+            id: Uuid::from_u128(42),
+        }
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let encoded = cursor().encode().unwrap();
+        let decoded = list_query(Some(encoded)).decode_cursor().unwrap().unwrap();
+
+        let expected = cursor();
+        assert_eq!(decoded.sort, expected.sort);
+        assert_eq!(decoded.order, expected.order);
+        assert_eq!(decoded.incurred_on, expected.incurred_on);
+        assert_eq!(decoded.created_at, expected.created_at);
+        assert_eq!(decoded.amount, expected.amount);
+        assert_eq!(decoded.id, expected.id);
+    }
+
+    #[test]
+    fn no_cursor_starts_at_the_first_page() {
+        assert!(list_query(None).decode_cursor().unwrap().is_none());
+    }
+
+    #[test]
+    fn malformed_cursors_are_refused() {
+        let mut truncated = cursor().encode().unwrap();
+        truncated.truncate(truncated.len() / 2);
+
+        for bad in [
+            "not a cursor!".to_string(),
+            URL_SAFE_NO_PAD.encode("not json"),
+            URL_SAFE_NO_PAD.encode(r#"{"sort":"amount","order":"asc"}"#),
+            truncated,
+        ] {
+            assert!(
+                matches!(
+                    list_query(Some(bad.clone())).decode_cursor(),
+                    Err(AppError::Validation(ValidationError::InvalidFormat(_)))
+                ),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn cursor_from_another_ordering_is_refused() {
+        let encoded = cursor().encode().unwrap();
+
+        let mut other_sort = list_query(Some(encoded.clone()));
+        other_sort.sort = ExpenseSortField::CreatedAt;
+        assert!(other_sort.decode_cursor().is_err());
+
+        let mut other_order = list_query(Some(encoded));
+        other_order.order = SortOrder::Desc;
+        assert!(other_order.decode_cursor().is_err());
+    }
+
+    #[test]
+    fn tampered_cursor_is_refused() {
+        let mut json: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(cursor().encode().unwrap()).unwrap())
+                .unwrap();
+        json["id"] = serde_json::json!("not-a-uuid");
+        let tampered = URL_SAFE_NO_PAD.encode(json.to_string());
+
+        assert!(list_query(Some(tampered)).decode_cursor().is_err());
+    }
+
+    fn dated_expense(
+        incurred_on: Option<NaiveDate>,
+        incurred_time: Option<NaiveTime>,
+        time_zone: Option<&str>,
+    ) -> CreateExpense {
+        CreateExpense {
+            amount: "10.00".parse().unwrap(),
+            currency: None,
+            description: "Lunch".to_string(),
+            category: None,
+            incurred_on,
+            incurred_time,
+            time_zone: time_zone.map(str::to_string),
+            split_type: SplitType::default(),
+            splits: Vec::new(),
+            participants: None,
+        }
+    }
+
+    fn today_in(zone: Tz) -> NaiveDate {
+        Utc::now().with_timezone(&zone).date_naive()
+    }
+
+    #[test]
+    fn date_defaults_to_today_where_the_payer_is() {
+        let zone = "Pacific/Kiritimati";
+        let date = dated_expense(None, None, Some(zone))
+            .expense_date(None)
+            .unwrap();
+
+        assert_eq!(date.incurred_on, today_in(zone.parse().unwrap()));
+        assert_eq!(date.time_zone.as_deref(), Some(zone));
+        assert!(date.incurred_at.is_none());
+    }
+
+    #[test]
+    fn date_after_today_in_the_time_zone_is_refused() {
+        let zone: Tz = "Pacific/Kiritimati".parse().unwrap();
+        let today = today_in(zone);
+
+        let accepted = dated_expense(Some(today), None, Some("Pacific/Kiritimati"));
+        assert_eq!(accepted.expense_date(None).unwrap().incurred_on, today);
+
+        let tomorrow = dated_expense(today.succ_opt(), None, Some("Pacific/Kiritimati"));
+        assert!(matches!(
+            tomorrow.expense_date(None),
+            Err(AppError::Validation(ValidationError::InvalidRange(_)))
+        ));
+    }
+
+    #[test]
+    fn date_without_time_zone_may_be_one_day_ahead_of_utc() {
+        let today = today_in(Tz::UTC);
+
+        let tomorrow = dated_expense(today.succ_opt(), None, None);
+        assert_eq!(
+            tomorrow.expense_date(None).unwrap().incurred_on,
+            today.succ_opt().unwrap()
+        );
+
+        let day_after = dated_expense(today.checked_add_days(chrono::Days::new(2)), None, None);
+        assert!(matches!(
+            day_after.expense_date(None),
+            Err(AppError::Validation(ValidationError::InvalidRange(_)))
+        ));
+    }
+
+    #[test]
+    fn invalid_time_zone_is_refused() {
+        for zone in ["Mars/Olympus_Mons", "UTC+2", ""] {
+            assert!(
+                matches!(
+                    dated_expense(None, None, Some(zone)).expense_date(None),
+                    Err(AppError::Validation(ValidationError::InvalidFormat(_)))
+                ),
+                "{zone}"
+            );
+        }
+    }
+
+    #[test]
+    fn local_time_is_stored_in_utc() {
+        let on = NaiveDate::from_ymd_opt(2025, 1, 15);
+        let at = NaiveTime::from_hms_opt(12, 30, 0);
+
+        let paris = dated_expense(on, at, Some("Europe/Paris"))
+            .expense_date(None)
+            .unwrap();
+        assert_eq!(
+            paris.incurred_at,
+            Some(Utc.with_ymd_and_hms(2025, 1, 15, 11, 30, 0).unwrap())
+        );
+
+        let utc = dated_expense(on, at, None).expense_date(None).unwrap();
+        assert_eq!(
+            utc.incurred_at,
+            Some(Utc.with_ymd_and_hms(2025, 1, 15, 12, 30, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn local_time_skipped_by_daylight_saving_is_refused() {
+        // Clocks in London went from 01:00 straight to 02:00 that morning
+        let expense = dated_expense(
+            NaiveDate::from_ymd_opt(2025, 3, 30),
+            NaiveTime::from_hms_opt(1, 30, 0),
+            Some("Europe/London"),
+        );
+        assert!(expense.expense_date(None).is_err());
+    }
 }
@@ -0,0 +1,303 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::{OnceCell, RwLock};
+
+use crate::errors::{AppError, AppResult, AuthError};
+
+/// Settings for signing in through an external OpenID Connect provider.
+/// Single sign-on stays off while `issuer_url` is empty.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcSettings {
+    pub issuer_url: String,
+    pub client_id: String,
+    /// Left out for public clients, which rely on PKCE alone.
+    pub client_secret: Option<String>,
+    /// Where the provider sends the user back to, usually a page of the web
+    /// app that passes `code` and `state` on to the callback endpoint.
+    pub redirect_url: String,
+    pub scopes: String,
+    /// Creates accounts for users the provider vouches for but who don't
+    /// have one yet, instead of turning them away.
+    pub auto_provision: bool,
+}
+
+impl Default for OidcSettings {
+    fn default() -> Self {
+        OidcSettings {
+            issuer_url: String::new(),
+            client_id: String::new(),
+            client_secret: None,
+            redirect_url: String::new(),
+            scopes: "openid email profile".to_string(),
+            auto_provision: false,
+        }
+    }
+}
+
+impl OidcSettings {
+    pub fn enabled(&self) -> bool {
+        !self.issuer_url.is_empty()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    preferred_username: Option<String>,
+}
+
+/// The user an identity provider vouched for in a verified ID token.
+#[derive(Debug)]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+}
+
+/// Talks to the identity provider. Its discovery document is fetched on
+/// first use; its signing keys are cached and fetched again when an ID token
+/// names a key that isn't known yet.
+pub struct OidcClient {
+    pub settings: OidcSettings,
+    http: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+impl OidcClient {
+    pub fn new(settings: OidcSettings) -> AppResult<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(10))
+            .build()
+            .map_err(|e| AppError::Internal(format!("Failed to build HTTP client: {}", e)))?;
+
+        Ok(OidcClient {
+            settings,
+            http,
+            metadata: OnceCell::new(),
+            jwks: RwLock::new(None),
+        })
+    }
+
+    async fn metadata(&self) -> AppResult<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.settings.issuer_url
+                );
+                let metadata: ProviderMetadata = self.get_json(&url).await?;
+                if metadata.issuer.trim_end_matches('/') != self.settings.issuer_url {
+                    return Err(AuthError::OidcError(format!(
+                        "discovery document names issuer {}",
+                        metadata.issuer
+                    ))
+                    .into());
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// The provider URL to send the user to, carrying the PKCE challenge for
+    /// `code_verifier`.
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> AppResult<String> {
+        let metadata = self.metadata().await?;
+        let mut url = Url::parse(&metadata.authorization_endpoint)
+            .map_err(|e| AuthError::OidcError(format!("invalid authorization endpoint: {}", e)))?;
+
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.settings.client_id)
+            .append_pair("redirect_uri", &self.settings.redirect_url)
+            .append_pair("scope", &self.settings.scopes)
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", &pkce_challenge(code_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.into())
+    }
+
+    /// Redeems an authorization code and returns the identity from the
+    /// verified ID token.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> AppResult<OidcIdentity> {
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.settings.redirect_url),
+            ("client_id", &self.settings.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &self.settings.client_secret {
+            form.push(("client_secret", secret));
+        }
+
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| AuthError::OidcError(format!("token request failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            log::warn!("OIDC token endpoint returned {}: {}", status, body);
+            return Err(AuthError::InvalidToken.into());
+        }
+
+        let tokens: TokenResponse = response
+            .json()
+            .await
+            .map_err(|e| AuthError::OidcError(format!("invalid token response: {}", e)))?;
+
+        let claims = self.verify_id_token(&tokens.id_token, metadata).await?;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(AuthError::InvalidToken.into());
+        }
+
+        Ok(OidcIdentity {
+            issuer: claims.iss,
+            subject: claims.sub,
+            email: claims.email,
+            email_verified: claims.email_verified,
+            preferred_username: claims.preferred_username,
+        })
+    }
+
+    async fn verify_id_token(
+        &self,
+        id_token: &str,
+        metadata: &ProviderMetadata,
+    ) -> AppResult<IdTokenClaims> {
+        let header = jsonwebtoken::decode_header(id_token).map_err(|_| AuthError::InvalidToken)?;
+        // Only accept signatures made with the provider's published keys
+        if !matches!(
+            header.alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+                | Algorithm::ES256
+                | Algorithm::ES384
+                | Algorithm::EdDSA
+        ) {
+            return Err(AuthError::InvalidToken.into());
+        }
+
+        let key = match self.decoding_key(header.kid.as_deref(), metadata).await? {
+            Some(key) => key,
+            None => {
+                // The provider may have rotated its keys since they were cached
+                self.refresh_jwks(metadata).await?;
+                self.decoding_key(header.kid.as_deref(), metadata)
+                    .await?
+                    .ok_or(AuthError::InvalidToken)?
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.settings.client_id]);
+
+        jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| {
+                log::warn!("Rejected OIDC ID token: {}", e);
+                AuthError::InvalidToken.into()
+            })
+    }
+
+    async fn decoding_key(
+        &self,
+        kid: Option<&str>,
+        metadata: &ProviderMetadata,
+    ) -> AppResult<Option<DecodingKey>> {
+        if self.jwks.read().await.is_none() {
+            self.refresh_jwks(metadata).await?;
+        }
+
+        let jwks = self.jwks.read().await;
+        let Some(jwks) = jwks.as_ref() else {
+            return Ok(None);
+        };
+        let jwk = match kid {
+            Some(kid) => jwks.find(kid),
+            // Without a kid, the provider has to have a single key
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        };
+
+        jwk.map(|jwk| {
+            DecodingKey::from_jwk(jwk)
+                .map_err(|e| AuthError::OidcError(format!("unusable provider key: {}", e)).into())
+        })
+        .transpose()
+    }
+
+    async fn refresh_jwks(&self, metadata: &ProviderMetadata) -> AppResult<()> {
+        let jwks: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+        *self.jwks.write().await = Some(jwks);
+        Ok(())
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> AppResult<T> {
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| AuthError::OidcError(format!("request to {} failed: {}", url, e)))?
+            .json()
+            .await
+            .map_err(|e| {
+                AuthError::OidcError(format!("invalid response from {}: {}", url, e)).into()
+            })
+    }
+}
+
+/// The S256 PKCE challenge for a code verifier.
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
@@ -0,0 +1,203 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::errors::{AppResult, AuthError};
+
+/// Keeps attempt counters and lockouts for the login throttle.
+///
+/// The default store lives in process memory, so each server instance counts
+/// on its own. Deployments running several instances can plug in a store
+/// backed by something shared instead.
+pub trait AttemptStore: Send + Sync {
+    /// Counts one attempt against `key` and returns how many it has had in
+    /// the current window. A window starts with the first attempt after the
+    /// previous one ran out.
+    fn hit(&self, key: &str, window: Duration) -> u32;
+
+    /// Blocks `key` for `duration`.
+    fn lock(&self, key: &str, duration: Duration);
+
+    /// How much longer `key` stays blocked, if it is blocked at all.
+    fn locked_for(&self, key: &str) -> Option<Duration>;
+
+    /// Forgets every attempt and lockout recorded for `key`.
+    fn clear(&self, key: &str);
+}
+
+struct Entry {
+    count: u32,
+    window_ends: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Entry {
+    fn is_stale(&self, now: Instant) -> bool {
+        self.window_ends <= now && self.locked_until.is_none_or(|until| until <= now)
+    }
+}
+
+/// Entries are pruned once the map grows past this many keys.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Default)]
+pub struct InMemoryAttemptStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl InMemoryAttemptStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AttemptStore for InMemoryAttemptStore {
+    fn hit(&self, key: &str, window: Duration) -> u32 {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        if entries.len() >= PRUNE_THRESHOLD {
+            entries.retain(|_, entry| !entry.is_stale(now));
+        }
+
+        let entry = entries.entry(key.to_string()).or_insert(Entry {
+            count: 0,
+            window_ends: now + window,
+            locked_until: None,
+        });
+        if entry.window_ends <= now {
+            entry.count = 0;
+            entry.window_ends = now + window;
+        }
+        entry.count += 1;
+        entry.count
+    }
+
+    fn lock(&self, key: &str, duration: Duration) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = entries.entry(key.to_string()).or_insert(Entry {
+            count: 0,
+            window_ends: now,
+            locked_until: None,
+        });
+        entry.locked_until = Some(now + duration);
+    }
+
+    fn locked_for(&self, key: &str) -> Option<Duration> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get(key)
+            .and_then(|entry| entry.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    fn clear(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.remove(key);
+    }
+}
+
+/// Limits for `LoginThrottle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThrottleConfig {
+    /// Requests each client IP may make per `ip_window_secs`.
+    pub ip_limit: u32,
+    pub ip_window_secs: u64,
+    /// Failed logins that lock an account when they fall within
+    /// `failure_window_minutes`.
+    pub max_failures: u32,
+    pub failure_window_minutes: u64,
+    pub lockout_minutes: u64,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        ThrottleConfig {
+            ip_limit: 20,
+            ip_window_secs: 60,
+            max_failures: 5,
+            failure_window_minutes: 15,
+            lockout_minutes: 15,
+        }
+    }
+}
+
+/// Throttles the login and registration endpoints.
+///
+/// Every client IP gets a fixed budget of requests per window. On top of
+/// that, an account that keeps failing to log in is locked for a while, no
+/// matter which addresses the attempts come from.
+pub struct LoginThrottle {
+    store: Arc<dyn AttemptStore>,
+    pub ip_limit: u32,
+    pub ip_window: Duration,
+    pub max_failures: u32,
+    pub failure_window: Duration,
+    pub lockout: Duration,
+}
+
+impl LoginThrottle {
+    pub fn new(store: Arc<dyn AttemptStore>, config: &ThrottleConfig) -> Self {
+        LoginThrottle {
+            store,
+            ip_limit: config.ip_limit,
+            ip_window: Duration::from_secs(config.ip_window_secs),
+            max_failures: config.max_failures,
+            failure_window: Duration::from_secs(config.failure_window_minutes * 60),
+            lockout: Duration::from_secs(config.lockout_minutes * 60),
+        }
+    }
+
+    /// Counts a request from `ip`, refusing it once the address has used up
+    /// its budget for the current window.
+    pub fn check_ip(&self, ip: &str) -> AppResult<()> {
+        let key = format!("ip:{}", ip);
+        Self::ensure_unlocked(self.store.as_ref(), &key)?;
+
+        if self.store.hit(&key, self.ip_window) > self.ip_limit {
+            self.store.lock(&key, self.ip_window);
+            return Err(too_many_attempts(self.ip_window));
+        }
+        Ok(())
+    }
+
+    pub fn check_account(&self, email: &str) -> AppResult<()> {
+        Self::ensure_unlocked(self.store.as_ref(), &account_key(email))
+    }
+
+    /// Records a failed login, locking the account once it has failed
+    /// `max_failures` times within `failure_window`.
+    pub fn record_failure(&self, email: &str) {
+        let key = account_key(email);
+        if self.store.hit(&key, self.failure_window) >= self.max_failures {
+            log::warn!("Locking account {} after repeated failed logins", email);
+            self.store.lock(&key, self.lockout);
+        }
+    }
+
+    pub fn record_success(&self, email: &str) {
+        self.store.clear(&account_key(email));
+    }
+
+    fn ensure_unlocked(store: &dyn AttemptStore, key: &str) -> AppResult<()> {
+        match store.locked_for(key) {
+            Some(remaining) => Err(too_many_attempts(remaining)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn account_key(email: &str) -> String {
+    format!("account:{}", email.trim().to_lowercase())
+}
+
+fn too_many_attempts(retry_after: Duration) -> crate::errors::AppError {
+    // Round up so clients never retry a moment too early
+    let retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    AuthError::TooManyAttempts(retry_after_secs).into()
+}
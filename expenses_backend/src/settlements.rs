@@ -0,0 +1,227 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::models::{Balance, Settlement};
+
+struct Party<'a> {
+    user_id: Uuid,
+    username: &'a str,
+    amount: Decimal,
+}
+
+/// Finds a small set of transfers that brings every balance back to zero.
+///
+/// Each round the member who is owed the most is paid by the member who owes
+/// the most, for as much as one of them can clear. Ties go to the lower user
+/// id so the same balances always give the same suggestions.
+pub fn simplify_debts(balances: &[Balance]) -> Vec<Settlement> {
+    let mut creditors: Vec<Party> = balances
+        .iter()
+        .filter(|b| b.balance > Decimal::ZERO)
+        .map(|b| Party {
+            user_id: b.user_id,
+            username: &b.username,
+            amount: b.balance,
+        })
+        .collect();
+    let mut debtors: Vec<Party> = balances
+        .iter()
+        .filter(|b| b.balance < Decimal::ZERO)
+        .map(|b| Party {
+            user_id: b.user_id,
+            username: &b.username,
+            amount: -b.balance,
+        })
+        .collect();
+
+    let mut settlements = Vec::new();
+    while let (Some(c), Some(d)) = (largest(&creditors), largest(&debtors)) {
+        let amount = creditors[c].amount.min(debtors[d].amount);
+        settlements.push(Settlement {
+            from_user_id: debtors[d].user_id,
+            from_username: debtors[d].username.to_string(),
+            to_user_id: creditors[c].user_id,
+            to_username: creditors[c].username.to_string(),
+            amount,
+        });
+
+        creditors[c].amount -= amount;
+        debtors[d].amount -= amount;
+        creditors.retain(|p| !p.amount.is_zero());
+        debtors.retain(|p| !p.amount.is_zero());
+    }
+    settlements
+}
+
+fn largest(parties: &[Party]) -> Option<usize> {
+    parties
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.amount.cmp(&b.amount).then(b.user_id.cmp(&a.user_id)))
+        .map(|(i, _)| i)
+}
+
+/// Nets individual debts between each pair of members.
+///
+/// `debts` holds `(from, to, amount)` entries, where `from` owes `to`. Debts
+/// in opposite directions cancel out, leaving at most one transfer per pair.
+pub fn net_pairwise_debts(
+    debts: &[(Uuid, Uuid, Decimal)],
+    usernames: &HashMap<Uuid, String>,
+) -> Vec<Settlement> {
+    let mut net: HashMap<(Uuid, Uuid), Decimal> = HashMap::new();
+    for &(from, to, amount) in debts {
+        if from == to {
+            continue;
+        }
+        if from < to {
+            *net.entry((from, to)).or_default() += amount;
+        } else {
+            *net.entry((to, from)).or_default() -= amount;
+        }
+    }
+
+    let mut pairs: Vec<((Uuid, Uuid), Decimal)> = net
+        .into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .collect();
+    pairs.sort_by_key(|(pair, _)| *pair);
+
+    pairs
+        .into_iter()
+        .map(|((a, b), amount)| {
+            let (from, to) = if amount > Decimal::ZERO {
+                (a, b)
+            } else {
+                (b, a)
+            };
+            Settlement {
+                from_user_id: from,
+                from_username: usernames.get(&from).cloned().unwrap_or_default(),
+                to_user_id: to,
+                to_username: usernames.get(&to).cloned().unwrap_or_default(),
+                amount: amount.abs(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn balances(values: &[(u128, &str)]) -> Vec<Balance> {
+        values
+            .iter()
+            .map(|&(id, balance)| Balance {
+                user_id: Uuid::from_u128(id),
+                username: format!("user{id}"),
+                balance: dec(balance),
+            })
+            .collect()
+    }
+
+    fn transfers(settlements: &[Settlement]) -> Vec<(u128, u128, Decimal)> {
+        settlements
+            .iter()
+            .map(|s| (s.from_user_id.as_u128(), s.to_user_id.as_u128(), s.amount))
+            .collect()
+    }
+
+    #[test]
+    fn simplified_transfers_clear_every_balance() {
+        let cases = [
+            balances(&[(1, "30.00"), (2, "-10.00"), (3, "-20.00")]),
+            balances(&[(1, "-45.50"), (2, "12.25"), (3, "33.25"), (4, "0.00")]),
+            balances(&[
+                (1, "7.01"),
+                (2, "-3.34"),
+                (3, "-3.33"),
+                (4, "5.00"),
+                (5, "-5.34"),
+            ]),
+        ];
+        for balances in cases {
+            let settlements = simplify_debts(&balances);
+
+            let mut net: HashMap<Uuid, Decimal> =
+                balances.iter().map(|b| (b.user_id, b.balance)).collect();
+            for s in &settlements {
+                assert!(s.amount > Decimal::ZERO);
+                *net.get_mut(&s.from_user_id).unwrap() += s.amount;
+                *net.get_mut(&s.to_user_id).unwrap() -= s.amount;
+            }
+            assert!(net.values().all(|balance| balance.is_zero()), "{net:?}");
+
+            let nonzero = balances.iter().filter(|b| !b.balance.is_zero()).count();
+            assert!(settlements.len() <= nonzero.saturating_sub(1));
+        }
+    }
+
+    #[test]
+    fn simplified_ties_go_to_the_lower_user_id() {
+        let expected = vec![(3, 1, dec("10.00")), (3, 2, dec("10.00"))];
+
+        let mut input = balances(&[(1, "10.00"), (2, "10.00"), (3, "-20.00")]);
+        assert_eq!(transfers(&simplify_debts(&input)), expected);
+
+        input.reverse();
+        assert_eq!(transfers(&simplify_debts(&input)), expected);
+    }
+
+    #[test]
+    fn simplified_zero_balances_need_no_transfers() {
+        assert!(simplify_debts(&[]).is_empty());
+        assert!(simplify_debts(&balances(&[(1, "0.00"), (2, "0.00")])).is_empty());
+    }
+
+    #[test]
+    fn pairwise_debts_cancel_out() {
+        let (a, b, c) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let debts = [
+            (b, a, dec("10.00")),
+            (a, b, dec("4.00")),
+            (c, a, dec("5.00")),
+            (a, c, dec("5.00")),
+            (c, c, dec("1.00")),
+        ];
+        let usernames = HashMap::from([(a, "a".to_string()), (b, "b".to_string())]);
+
+        let settlements = net_pairwise_debts(&debts, &usernames);
+
+        assert_eq!(transfers(&settlements), vec![(2, 1, dec("6.00"))]);
+        assert_eq!(settlements[0].from_username, "b");
+        assert_eq!(settlements[0].to_username, "a");
+    }
+
+    #[test]
+    fn pairwise_debts_are_sorted_by_pair() {
+        let ids: Vec<Uuid> = (1..=3).map(Uuid::from_u128).collect();
+        let mut debts = vec![
+            (ids[2], ids[1], dec("1.00")),
+            (ids[0], ids[2], dec("2.00")),
+            (ids[1], ids[0], dec("3.00")),
+        ];
+        let expected = vec![
+            (2, 1, dec("3.00")),
+            (1, 3, dec("2.00")),
+            (3, 2, dec("1.00")),
+        ];
+
+        assert_eq!(
+            transfers(&net_pairwise_debts(&debts, &HashMap::new())),
+            expected
+        );
+        debts.reverse();
+        assert_eq!(
+            transfers(&net_pairwise_debts(&debts, &HashMap::new())),
+            expected
+        );
+        assert!(net_pairwise_debts(&[], &HashMap::new()).is_empty());
+    }
+}
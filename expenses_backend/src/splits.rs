@@ -0,0 +1,257 @@
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+use uuid::Uuid;
+
+use crate::errors::{AppResult, ExpenseError, ValidationError};
+use crate::models::{SplitEntry, SplitType};
+
+#[derive(Debug)]
+pub struct ComputedSplit {
+    pub user_id: Uuid,
+    pub amount: Decimal,
+    pub share: Option<Decimal>,
+}
+
+/// Divides `total` into cent amounts proportional to `weights`.
+///
+/// Every portion is rounded down to the cent, then the leftover cents are
+/// handed out one at a time to the largest remainders. Ties go to the earlier
+/// entry, so callers should pass weights in a stable order.
+pub fn allocate(total: Decimal, weights: &[Decimal]) -> Vec<Decimal> {
+    let weight_sum: Decimal = weights.iter().sum();
+    if weights.is_empty() || weight_sum.is_zero() {
+        return vec![Decimal::ZERO; weights.len()];
+    }
+
+    let mut portions: Vec<(Decimal, Decimal)> = weights
+        .iter()
+        .map(|w| {
+            let exact = total * w / weight_sum;
+            let rounded = exact.round_dp_with_strategy(2, RoundingStrategy::ToZero);
+            (rounded, exact - rounded)
+        })
+        .collect();
+
+    let allocated: Decimal = portions.iter().map(|(amount, _)| amount).sum();
+    let leftover_cents = ((total - allocated) * Decimal::ONE_HUNDRED)
+        .to_usize()
+        .unwrap_or(0);
+
+    let mut order: Vec<usize> = (0..portions.len()).collect();
+    order.sort_by(|&a, &b| portions[b].1.cmp(&portions[a].1));
+    for &i in order.iter().take(leftover_cents) {
+        portions[i].0 += Decimal::new(1, 2);
+    }
+
+    portions.into_iter().map(|(amount, _)| amount).collect()
+}
+
+/// Works out each participant's portion of an expense of `total`.
+///
+/// Equal splits are spread over `participants`. The other modes take their
+/// values from `entries`, which must name exactly the same participants.
+pub fn compute_splits(
+    total: Decimal,
+    split_type: SplitType,
+    participants: &[Uuid],
+    entries: &[SplitEntry],
+) -> AppResult<Vec<ComputedSplit>> {
+    match split_type {
+        SplitType::Equal => {
+            let mut participants = participants.to_vec();
+            participants.sort();
+            let weights = vec![Decimal::ONE; participants.len()];
+            let amounts = allocate(total, &weights);
+            Ok(participants
+                .into_iter()
+                .zip(amounts)
+                .map(|(user_id, amount)| ComputedSplit {
+                    user_id,
+                    amount,
+                    share: None,
+                })
+                .collect())
+        }
+        SplitType::Exact => {
+            let values = entry_values(participants, entries)?;
+            if values.iter().any(|(_, value)| value.scale() > 2) {
+                return Err(ValidationError::InvalidFormat(
+                    "split amounts with at most 2 decimal places".to_string(),
+                )
+                .into());
+            }
+            let value_sum: Decimal = values.iter().map(|(_, value)| value).sum();
+            if value_sum != total {
+                return Err(ExpenseError::InvalidAmount(format!(
+                    "split amounts add up to {}, expected {}",
+                    value_sum, total
+                ))
+                .into());
+            }
+            Ok(values
+                .into_iter()
+                .map(|(user_id, value)| ComputedSplit {
+                    user_id,
+                    amount: value,
+                    share: None,
+                })
+                .collect())
+        }
+        SplitType::Percentage | SplitType::Shares => {
+            let values = entry_values(participants, entries)?;
+            if values.iter().any(|(_, value)| value.scale() > 4) {
+                return Err(ValidationError::InvalidFormat(
+                    "split values with at most 4 decimal places".to_string(),
+                )
+                .into());
+            }
+            let value_sum: Decimal = values.iter().map(|(_, value)| value).sum();
+            if split_type == SplitType::Percentage && value_sum != Decimal::ONE_HUNDRED {
+                return Err(ExpenseError::InvalidAmount(format!(
+                    "percentages add up to {}, expected 100",
+                    value_sum
+                ))
+                .into());
+            }
+            let weights: Vec<Decimal> = values.iter().map(|(_, value)| *value).collect();
+            let amounts = allocate(total, &weights);
+            Ok(values
+                .into_iter()
+                .zip(amounts)
+                .map(|((user_id, value), amount)| ComputedSplit {
+                    user_id,
+                    amount,
+                    share: Some(value),
+                })
+                .collect())
+        }
+    }
+}
+
+fn entry_values(participants: &[Uuid], entries: &[SplitEntry]) -> AppResult<Vec<(Uuid, Decimal)>> {
+    if entries.len() != participants.len()
+        || entries.iter().any(|e| !participants.contains(&e.user_id))
+    {
+        return Err(
+            ValidationError::InvalidFormat("one split for each participant".to_string()).into(),
+        );
+    }
+
+    let mut values = Vec::with_capacity(entries.len());
+    for entry in entries {
+        values.push((entry.user_id, entry.value));
+    }
+    values.sort_by_key(|(user_id, _)| *user_id);
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn ids(count: u128) -> Vec<Uuid> {
+        (1..=count).map(Uuid::from_u128).collect()
+    }
+
+    #[test]
+    fn allocate_splits_100_three_ways() {
+        let amounts = allocate(dec("100.00"), &[Decimal::ONE; 3]);
+        assert_eq!(amounts, vec![dec("33.34"), dec("33.33"), dec("33.33")]);
+    }
+
+    #[test]
+    fn allocate_gives_leftover_cents_to_largest_remainders() {
+        // The exact portions are 0.1666.. three times and 0.50, so two cents
+        // are left over and the tied remainders go to the earlier entries.
+        let amounts = allocate(dec("1.00"), &[dec("1"), dec("1"), dec("1"), dec("3")]);
+        assert_eq!(
+            amounts,
+            vec![dec("0.17"), dec("0.17"), dec("0.16"), dec("0.50")]
+        );
+    }
+
+    #[test]
+    fn allocate_always_adds_up_to_total() {
+        let cases = [
+            ("100.00", vec!["1", "1", "1"]),
+            ("0.01", vec!["1", "1", "1"]),
+            ("10.01", vec!["50", "30", "20"]),
+            ("99.99", vec!["33.3333", "33.3333", "33.3334"]),
+            ("1234.57", vec!["1", "2", "3", "4", "5", "6", "7"]),
+            ("0.00", vec!["1", "1"]),
+        ];
+        for (total, weights) in cases {
+            let weights: Vec<Decimal> = weights.into_iter().map(dec).collect();
+            let amounts = allocate(dec(total), &weights);
+            assert_eq!(amounts.len(), weights.len());
+            assert_eq!(amounts.iter().sum::<Decimal>(), dec(total), "{total}");
+        }
+    }
+
+    #[test]
+    fn allocate_without_weight_gives_nothing() {
+        assert!(allocate(dec("10.00"), &[]).is_empty());
+        assert_eq!(
+            allocate(dec("10.00"), &[Decimal::ZERO; 2]),
+            vec![Decimal::ZERO; 2]
+        );
+    }
+
+    #[test]
+    fn percentage_split_rounds_remainder_to_largest_share() {
+        let participants = ids(3);
+        let entries: Vec<SplitEntry> = participants
+            .iter()
+            .zip(["50", "30", "20"])
+            .map(|(user_id, value)| SplitEntry {
+                user_id: *user_id,
+                value: dec(value),
+            })
+            .collect();
+
+        let splits =
+            compute_splits(dec("10.01"), SplitType::Percentage, &participants, &entries).unwrap();
+
+        let amounts: Vec<Decimal> = splits.iter().map(|s| s.amount).collect();
+        assert_eq!(amounts, vec![dec("5.01"), dec("3.00"), dec("2.00")]);
+        assert_eq!(splits[0].share, Some(dec("50")));
+    }
+
+    #[test]
+    fn percentage_split_must_add_up_to_100() {
+        let participants = ids(2);
+        let entries: Vec<SplitEntry> = participants
+            .iter()
+            .map(|user_id| SplitEntry {
+                user_id: *user_id,
+                value: dec("40"),
+            })
+            .collect();
+
+        assert!(
+            compute_splits(dec("10.00"), SplitType::Percentage, &participants, &entries).is_err()
+        );
+    }
+
+    #[test]
+    fn equal_split_breaks_ties_by_user_id() {
+        let mut participants = ids(3);
+        participants.reverse();
+
+        let splits = compute_splits(dec("100.00"), SplitType::Equal, &participants, &[]).unwrap();
+
+        let result: Vec<(Uuid, Decimal)> = splits.iter().map(|s| (s.user_id, s.amount)).collect();
+        assert_eq!(
+            result,
+            vec![
+                (Uuid::from_u128(1), dec("33.34")),
+                (Uuid::from_u128(2), dec("33.33")),
+                (Uuid::from_u128(3), dec("33.33")),
+            ]
+        );
+    }
+}
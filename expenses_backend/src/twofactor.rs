@@ -0,0 +1,84 @@
+use rand::RngCore;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::errors::{AppError, AppResult};
+
+const ISSUER: &str = "Expense Share";
+const DIGITS: usize = 6;
+const STEP_SECONDS: u64 = 30;
+/// Codes from this many steps either side of the current one are accepted,
+/// to allow for clock drift on the user's device.
+const SKEW_STEPS: u64 = 1;
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Generates a new random TOTP secret, base32 encoded the way authenticator
+/// apps expect it.
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn totp(secret: &str, account_name: &str) -> AppResult<TOTP> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| AppError::Internal(format!("Invalid TOTP secret: {}", e)))?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        DIGITS,
+        0,
+        STEP_SECONDS,
+        bytes,
+        Some(ISSUER.to_string()),
+        account_name.to_string(),
+    )
+    .map_err(|e| AppError::Internal(format!("Invalid TOTP parameters: {}", e)))
+}
+
+/// The `otpauth://` URI an authenticator app is set up from, usually shown
+/// to the user as a QR code.
+pub fn provisioning_uri(secret: &str, account_name: &str) -> AppResult<String> {
+    Ok(totp(secret, account_name)?.get_url())
+}
+
+/// Checks `code` against `secret` at `now` (Unix seconds) and returns the
+/// time step it was generated for. Callers store the step so the same code
+/// can't be used twice.
+pub fn verify_code(secret: &str, code: &str, now: u64) -> AppResult<Option<i64>> {
+    let totp = totp(secret, "")?;
+    let current = now / STEP_SECONDS;
+
+    for step in current.saturating_sub(SKEW_STEPS)..=current + SKEW_STEPS {
+        if totp.check(code, step * STEP_SECONDS) {
+            return Ok(Some(step as i64));
+        }
+    }
+    Ok(None)
+}
+
+/// Generates a fresh set of recovery codes, formatted as four groups of four
+/// hex digits.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 8];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let digits = hex::encode(bytes);
+            format!(
+                "{}-{}-{}-{}",
+                &digits[0..4],
+                &digits[4..8],
+                &digits[8..12],
+                &digits[12..16]
+            )
+        })
+        .collect()
+}
+
+/// Brings a recovery code into the form it is hashed in, so users can type
+/// it without dashes or in upper case.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
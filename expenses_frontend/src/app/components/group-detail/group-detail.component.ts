@@ -15,6 +15,7 @@ import { User } from '../../models/user.interface';
 })
 export class GroupDetailComponent implements OnInit {
   expenses: any[] = [];
+  expensesCursor: string | null = null;
   
   // Epsilon for floating-point comparisons (0.01 = 1 cent precision)
   // This prevents issues with tiny precision errors like 0.00001 being treated as non-zero
@@ -22,8 +23,24 @@ export class GroupDetailComponent implements OnInit {
   
   loadExpenses() {
     this.apiService.getGroupExpenses(this.groupId).subscribe({
-      next: (expenses) => {
-        this.expenses = expenses;
+      next: (page) => {
+        this.expenses = page.expenses;
+        this.expensesCursor = page.pagination.next_cursor;
+      },
+      error: (error) => {
+        console.error('Error loading expenses:', error);
+      }
+    });
+  }
+
+  loadMoreExpenses() {
+    if (!this.expensesCursor) {
+      return;
+    }
+    this.apiService.getGroupExpenses(this.groupId, this.expensesCursor).subscribe({
+      next: (page) => {
+        this.expenses = [...this.expenses, ...page.expenses];
+        this.expensesCursor = page.pagination.next_cursor;
       },
       error: (error) => {
         console.error('Error loading expenses:', error);
@@ -113,7 +130,13 @@ export class GroupDetailComponent implements OnInit {
     this.expenseError = '';
     this.expenseSuccess = '';
 
-    this.apiService.addExpense(this.groupId, this.newExpense).subscribe({
+    const expense: CreateExpense = {
+      ...this.newExpense,
+      incurred_on: this.newExpense.incurred_on || undefined,
+      time_zone: Intl.DateTimeFormat().resolvedOptions().timeZone
+    };
+
+    this.apiService.addExpense(this.groupId, expense).subscribe({
       next: () => {
         this.isAddingExpense = false;
         this.expenseSuccess = `Expense "${this.newExpense.description}" added successfully!`;
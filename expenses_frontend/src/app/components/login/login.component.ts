@@ -1,5 +1,5 @@
 import { Component } from '@angular/core';
-import { Router } from '@angular/router';
+import { ActivatedRoute, Router } from '@angular/router';
 import { CommonModule } from '@angular/common';
 import { FormsModule } from '@angular/forms';
 import { ApiService } from '../../services/api.service';
@@ -20,17 +20,64 @@ export class LoginComponent {
   
   isLoading = false;
   errorMessage = '';
+  challengeToken = '';
+  twoFactorCode = '';
 
   constructor(
     private apiService: ApiService,
-    private router: Router
+    private router: Router,
+    private route: ActivatedRoute
   ) {
     // Redirect if already logged in
     if (this.apiService.isLoggedIn()) {
       this.redirectBasedOnRole();
+      return;
+    }
+
+    // The identity provider sends single sign-on users back here
+    const params = this.route.snapshot.queryParamMap;
+    const code = params.get('code');
+    const state = params.get('state');
+    if (code && state) {
+      this.completeSso(code, state);
     }
   }
 
+  onSso() {
+    this.isLoading = true;
+    this.errorMessage = '';
+
+    this.apiService.getOidcAuthorizationUrl().subscribe({
+      next: (url) => {
+        window.location.href = url;
+      },
+      error: (error: any) => {
+        this.isLoading = false;
+        this.errorMessage = error.error?.message || 'Single sign-on is unavailable.';
+      }
+    });
+  }
+
+  private completeSso(code: string, state: string) {
+    this.isLoading = true;
+
+    this.apiService.completeOidcLogin(code, state).subscribe({
+      next: (response) => {
+        this.isLoading = false;
+        if (response.two_factor_required && response.challenge_token) {
+          this.challengeToken = response.challenge_token;
+          return;
+        }
+        this.redirectBasedOnRole();
+      },
+      error: (error: any) => {
+        this.isLoading = false;
+        this.errorMessage = error.error?.message || 'Single sign-on failed. Please try again.';
+        this.router.navigate(['/login']);
+      }
+    });
+  }
+
   onSubmit() {
     if (!this.loginData.email || !this.loginData.password) {
       this.errorMessage = 'Please fill in all fields';
@@ -45,6 +92,10 @@ export class LoginComponent {
       next: (response) => {
         console.log('Login successful in component:', response);
         this.isLoading = false;
+        if (response.two_factor_required && response.challenge_token) {
+          this.challengeToken = response.challenge_token;
+          return;
+        }
         this.redirectBasedOnRole();
       },
       error: (error: any) => {
@@ -55,6 +106,32 @@ export class LoginComponent {
     });
   }
 
+  onSubmitCode() {
+    if (!this.twoFactorCode) {
+      this.errorMessage = 'Please enter your authentication code';
+      return;
+    }
+
+    this.isLoading = true;
+    this.errorMessage = '';
+
+    this.apiService.completeTwoFactorLogin(this.challengeToken, this.twoFactorCode).subscribe({
+      next: () => {
+        this.isLoading = false;
+        this.redirectBasedOnRole();
+      },
+      error: (error: any) => {
+        this.isLoading = false;
+        this.errorMessage = error.error?.message || 'Verification failed. Please try again.';
+        // An expired challenge means starting over from the password
+        if (error.error?.code === 'AUTH_TOKEN_EXPIRED') {
+          this.challengeToken = '';
+          this.twoFactorCode = '';
+        }
+      }
+    });
+  }
+
   private redirectBasedOnRole() {
     console.log('Checking user role for redirect...');
     console.log('Current user:', this.apiService.getCurrentUser());
@@ -19,6 +19,18 @@ export interface Balance {
 export interface CreateExpense {
   amount: number;
   description: string;
+  incurred_on?: string;
+  time_zone?: string;
+}
+
+export interface ExpensePage {
+  expenses: any[];
+  pagination: {
+    limit: number;
+    total: number;
+    has_more: boolean;
+    next_cursor: string | null;
+  };
 }
 
 export interface CreatePayment {
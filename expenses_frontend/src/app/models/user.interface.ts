@@ -3,6 +3,7 @@ export interface User {
   email: string;
   username: string;
   is_admin: boolean;
+  email_verified?: boolean;
 }
 
 export interface LoginRequest {
@@ -12,8 +13,19 @@ export interface LoginRequest {
 
 export interface LoginResponse {
   message?: string;
+  token?: string;
+  refresh_token?: string;
+  expires_in?: number;
+  user?: User;
+  // Set instead of the tokens when the account has two-factor authentication
+  two_factor_required?: boolean;
+  challenge_token?: string;
+}
+
+export interface RefreshResponse {
   token: string;
-  user: User;
+  refresh_token: string;
+  expires_in: number;
 }
 
 export interface CreateUserRequest {
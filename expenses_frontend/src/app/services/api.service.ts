@@ -1,18 +1,20 @@
 import { Injectable } from '@angular/core';
 import { HttpClient, HttpHeaders } from '@angular/common/http';
 import { Observable, BehaviorSubject, throwError } from 'rxjs';
-import { tap, catchError } from 'rxjs/operators';
-import { User, LoginRequest, LoginResponse, CreateUserRequest } from '../models/user.interface';
-import { Group, CreateGroupRequest, Balance, CreateExpense, CreatePayment } from '../models/group.interface';
+import { tap, catchError, map } from 'rxjs/operators';
+import { User, LoginRequest, LoginResponse, RefreshResponse, CreateUserRequest } from '../models/user.interface';
+import { Group, CreateGroupRequest, Balance, CreateExpense, CreatePayment, ExpensePage } from '../models/group.interface';
 import { environment } from '../../environments/environment';
 
 @Injectable({
   providedIn: 'root'
 })
 export class ApiService {
-  getGroupExpenses(groupId: string): Observable<any[]> {
-    return this.http.get<any[]>(`${this.baseUrl}/groups/${groupId}/expenses`, {
-      headers: this.getAuthHeaders()
+  getGroupExpenses(groupId: string, cursor?: string): Observable<ExpensePage> {
+    const params: Record<string, string> = cursor ? { cursor } : {};
+    return this.http.get<ExpensePage>(`${this.baseUrl}/groups/${groupId}/expenses`, {
+      headers: this.getAuthHeaders(),
+      params
     }).pipe(
       catchError(this.handleError)
     );
@@ -44,9 +46,7 @@ export class ApiService {
     return this.http.post<LoginResponse>(`${this.baseUrl}/auth/login`, request).pipe(
       tap(response => {
         console.log('Login response received:', response);
-        this.setToken(response.token);
-        this.setUser(response.user);
-        this.currentUserSubject.next(response.user);
+        this.storeLogin(response);
       }),
       catchError((error) => {
         console.error('Login error in service:', error);
@@ -55,11 +55,79 @@ export class ApiService {
     );
   }
 
+  // Second login step for accounts with two-factor authentication
+  completeTwoFactorLogin(challengeToken: string, code: string): Observable<LoginResponse> {
+    return this.http.post<LoginResponse>(`${this.baseUrl}/auth/login/2fa`, {
+      challenge_token: challengeToken,
+      code
+    }).pipe(
+      tap(response => this.storeLogin(response)),
+      catchError((error) => throwError(() => error))
+    );
+  }
+
+  // Single sign-on: fetch the identity provider URL to send the user to
+  getOidcAuthorizationUrl(): Observable<string> {
+    // The API sets a cookie tying the login to this browser
+    return this.http.get<{ authorization_url: string }>(`${this.baseUrl}/auth/oidc/authorize`, {
+      withCredentials: true
+    }).pipe(
+      map(response => response.authorization_url)
+    );
+  }
+
+  // Single sign-on: finish the login with what the provider redirected back with
+  completeOidcLogin(code: string, state: string): Observable<LoginResponse> {
+    return this.http.post<LoginResponse>(`${this.baseUrl}/auth/oidc/callback`, { code, state }, {
+      withCredentials: true
+    }).pipe(
+      tap(response => this.storeLogin(response)),
+      catchError((error) => throwError(() => error))
+    );
+  }
+
+  private storeLogin(response: LoginResponse): void {
+    if (!response.token || !response.refresh_token || !response.user) {
+      return;
+    }
+    this.setToken(response.token);
+    this.setRefreshToken(response.refresh_token);
+    this.setUser(response.user);
+    this.currentUserSubject.next(response.user);
+  }
+
   logout(): void {
+    if (this.getToken()) {
+      // Revoke the session server-side; local state is cleared regardless
+      this.http.post(`${this.baseUrl}/auth/logout`, {}, {
+        headers: this.getAuthHeaders()
+      }).subscribe({ error: () => {} });
+    }
     this.clearAuth();
     this.currentUserSubject.next(null);
   }
 
+  // Trades the stored refresh token for a new access token
+  refreshSession(): Observable<string> {
+    const refreshToken = this.getRefreshToken();
+    if (!refreshToken) {
+      return throwError(() => new Error('No refresh token'));
+    }
+
+    return this.http.post<RefreshResponse>(`${this.baseUrl}/auth/refresh`, { refresh_token: refreshToken }).pipe(
+      tap(response => {
+        this.setToken(response.token);
+        this.setRefreshToken(response.refresh_token);
+      }),
+      map(response => response.token),
+      catchError((error) => {
+        this.clearAuth();
+        this.currentUserSubject.next(null);
+        return throwError(() => error);
+      })
+    );
+  }
+
   isLoggedIn(): boolean {
     return !!this.getToken();
   }
@@ -82,12 +150,21 @@ export class ApiService {
     localStorage.setItem('token', token);
   }
 
+  private getRefreshToken(): string | null {
+    return localStorage.getItem('refresh_token');
+  }
+
+  private setRefreshToken(token: string): void {
+    localStorage.setItem('refresh_token', token);
+  }
+
   private setUser(user: User): void {
     localStorage.setItem('user', JSON.stringify(user));
   }
 
   private clearAuth(): void {
     localStorage.removeItem('token');
+    localStorage.removeItem('refresh_token');
     localStorage.removeItem('user');
   }
 
@@ -141,15 +218,18 @@ export class ApiService {
   }
 
   getGroupBalances(groupId: string): Observable<Balance[]> {
-    return this.http.get<Balance[]>(`${this.baseUrl}/groups/${groupId}/balances`, {
+    // Balances are sent as decimal strings to keep them exact
+    return this.http.get<any[]>(`${this.baseUrl}/groups/${groupId}/balances`, {
       headers: this.getAuthHeaders()
     }).pipe(
+      map(balances => balances.map(b => ({ ...b, balance: Number(b.balance) }) as Balance)),
       catchError(this.handleError)
     );
   }
 
   addExpense(groupId: string, expense: CreateExpense): Observable<any> {
-    return this.http.post(`${this.baseUrl}/groups/${groupId}/expenses`, expense, {
+    const body = { ...expense, amount: expense.amount.toFixed(2) };
+    return this.http.post(`${this.baseUrl}/groups/${groupId}/expenses`, body, {
       headers: this.getAuthHeaders()
     }).pipe(
       catchError(this.handleError)
@@ -157,7 +237,8 @@ export class ApiService {
   }
 
   recordPayment(groupId: string, payment: CreatePayment): Observable<any> {
-    return this.http.post(`${this.baseUrl}/groups/${groupId}/payments`, payment, {
+    const body = { ...payment, amount: payment.amount.toFixed(2) };
+    return this.http.post(`${this.baseUrl}/groups/${groupId}/payments`, body, {
       headers: this.getAuthHeaders()
     }).pipe(
       catchError(this.handleError)
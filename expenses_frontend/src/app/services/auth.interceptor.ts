@@ -0,0 +1,28 @@
+import { Injectable, Injector } from '@angular/core';
+import { HttpErrorResponse, HttpEvent, HttpHandler, HttpInterceptor, HttpRequest } from '@angular/common/http';
+import { Observable, throwError } from 'rxjs';
+import { catchError, switchMap } from 'rxjs/operators';
+import { ApiService } from './api.service';
+
+// Renews an expired access token once and replays the failed request
+@Injectable()
+export class AuthInterceptor implements HttpInterceptor {
+  constructor(private injector: Injector) {}
+
+  intercept(req: HttpRequest<any>, next: HttpHandler): Observable<HttpEvent<any>> {
+    return next.handle(req).pipe(
+      catchError((error: HttpErrorResponse) => {
+        const expired = error.status === 401 && error.error?.code === 'AUTH_TOKEN_EXPIRED';
+        if (!expired || req.url.includes('/auth/')) {
+          return throwError(() => error);
+        }
+
+        // Resolved lazily: ApiService depends on HttpClient, which depends on this interceptor
+        const api = this.injector.get(ApiService);
+        return api.refreshSession().pipe(
+          switchMap(token => next.handle(req.clone({ setHeaders: { Authorization: `Bearer ${token}` } })))
+        );
+      })
+    );
+  }
+}
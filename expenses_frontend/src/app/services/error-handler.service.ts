@@ -57,6 +57,10 @@ export class ErrorHandlerService {
         return 'Authentication configuration error. Please contact support.';
       case 'AUTH_INSUFFICIENT_PERMISSIONS':
         return 'You do not have permission to perform this action.';
+      case 'AUTH_TOO_MANY_ATTEMPTS':
+        return error.message || 'Too many attempts. Please wait before trying again.';
+      case 'AUTH_INVALID_2FA_CODE':
+        return 'That code is incorrect. Please check your authenticator app and try again.';
       case 'USER_EMAIL_ALREADY_EXISTS':
         return 'An account with this email already exists.';
       case 'USER_USERNAME_ALREADY_EXISTS':